@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Context, Result};
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use crate::utils::log;
+use crate::utils::net;
+use crate::utils::resolve::HostAddr;
+
+/// Delivery figures of one destination of the emitter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DestinationStatus {
+    /// The hostname `addr` was resolved from, if any.
+    pub host: Option<String>,
+    pub addr: SocketAddr,
+    pub packets_sent: u64,
+    pub send_errors: u64,
+    /// The last send error, cleared once a packet goes through again.
+    pub last_error: Option<String>,
+}
+
+struct Destination {
+    target: HostAddr,
+    // Every address of the target, packets going to the first one the socket
+    // can reach
+    addrs: Vec<SocketAddr>,
+    status: DestinationStatus,
+}
+
+/// The destinations every packet is sent to, shared between the emitter and
+/// its sender thread so they can change while streaming.
+#[derive(Clone, Default)]
+pub struct Destinations(Arc<Mutex<Vec<Destination>>>);
+
+impl Destinations {
+    pub fn new(targets: &[HostAddr]) -> Result<Self> {
+        let destinations = Self::default();
+        for target in targets {
+            destinations.add(target.clone())?;
+        }
+
+        Ok(destinations)
+    }
+
+    /// Adds a destination, resolving it when given by hostname. Returns false
+    /// when it was already there.
+    pub fn add(&self, target: HostAddr) -> Result<bool> {
+        if self.contains(&target) {
+            return Ok(false);
+        }
+        // Looked up without holding the lock, which would stall the sender
+        let addrs = target.resolve()?;
+        let mut destinations = self.0.lock().unwrap();
+        if destinations
+            .iter()
+            .any(|destination| destination.target == target)
+        {
+            return Ok(false);
+        }
+        destinations.push(Destination {
+            status: DestinationStatus {
+                host: match &target {
+                    HostAddr::Name(host, _) => Some(host.clone()),
+                    HostAddr::Ip(_) => None,
+                },
+                addr: addrs[0],
+                packets_sent: 0,
+                send_errors: 0,
+                last_error: None,
+            },
+            target,
+            addrs,
+        });
+
+        Ok(true)
+    }
+
+    pub fn contains(&self, target: &HostAddr) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|destination| destination.target == *target)
+    }
+
+    /// Removes a destination, returning false when it was not there.
+    pub fn remove(&self, target: &HostAddr) -> bool {
+        let mut destinations = self.0.lock().unwrap();
+        let len = destinations.len();
+        destinations.retain(|destination| destination.target != *target);
+
+        destinations.len() != len
+    }
+
+    /// Resolves the destinations given by hostname again, keeping their
+    /// previous addresses when the lookup fails.
+    pub fn refresh(&self) {
+        let targets = self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|destination| destination.target.clone())
+            .filter(|target| matches!(target, HostAddr::Name(..)))
+            .collect::<Vec<_>>();
+
+        // Looked up without holding the lock, which would stall the sender
+        for target in targets {
+            let result = target.resolve();
+            let mut destinations = self.0.lock().unwrap();
+            let Some(destination) = destinations
+                .iter_mut()
+                .find(|destination| destination.target == target)
+            else {
+                continue;
+            };
+            match result {
+                Ok(addrs) if !same_addrs(&addrs, &destination.addrs) => {
+                    log::info(&format!("{} now resolves to {:?}", target, addrs));
+                    destination.addrs = addrs;
+                }
+                Ok(_) => {}
+                Err(e) => log::warn(&format!(
+                    "{:#}, still sending to {}",
+                    e, destination.status.addr
+                )),
+            }
+        }
+    }
+
+    /// Whether a destination is given by hostname, to be resolved again.
+    pub fn has_hosts(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|destination| matches!(destination.target, HostAddr::Name(..)))
+    }
+
+    /// Whether a destination only has IPv6 addresses, so the emitter needs
+    /// an IPv6 socket.
+    pub fn requires_ipv6(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|destination| !destination.addrs.iter().any(SocketAddr::is_ipv4))
+    }
+
+    pub fn status(&self) -> Vec<DestinationStatus> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|destination| destination.status.clone())
+            .collect()
+    }
+
+    /// Sends a packet to every destination, so a failing one never holds
+    /// the others back. IPv4 destinations are reached through their mapped
+    /// address from an `ipv6` dual-stack socket.
+    pub fn send(&self, socket: &UdpSocket, ipv6: bool, packet: &[u8]) {
+        for destination in self.0.lock().unwrap().iter_mut() {
+            let status = &mut destination.status;
+            match send_to(socket, ipv6, &destination.addrs, &mut status.addr, packet) {
+                Ok(_) => {
+                    status.packets_sent += 1;
+                    status.last_error = None;
+                }
+                Err(e) => {
+                    status.send_errors += 1;
+                    // Log each error once rather than per packet
+                    let error = e.to_string();
+                    if status.last_error.as_ref() != Some(&error) {
+                        log::error(&format!(
+                            "error sending data to {}: {}",
+                            destination.target, error
+                        ));
+                        status.last_error = Some(error);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sends a packet to the first of `addrs` an `ipv6` or IPv4 socket can
+/// reach, recording it in `addr`.
+fn send_to(
+    socket: &UdpSocket,
+    ipv6: bool,
+    addrs: &[SocketAddr],
+    addr: &mut SocketAddr,
+    packet: &[u8],
+) -> io::Result<usize> {
+    *addr = addrs
+        .iter()
+        .copied()
+        .find(|addr| ipv6 || addr.is_ipv4())
+        .ok_or(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "no IPv4 address",
+        ))?;
+
+    socket.send_to(packet, net::socket_family_addr(*addr, ipv6))
+}
+
+/// Whether two lookups returned the same addresses, whatever their order.
+fn same_addrs(a: &[SocketAddr], b: &[SocketAddr]) -> bool {
+    a.len() == b.len() && a.iter().all(|addr| b.contains(addr))
+}
+
+/// Checks that a socket bound to `bind_addr` can send to `target`, IPv4
+/// sockets never reaching IPv6 addresses and IPv6 only sockets never
+/// reaching IPv4 ones. Hostnames are checked once resolved, when sending.
+pub fn check_reachable(bind_addr: SocketAddr, ipv6_only: bool, target: &HostAddr) -> Result<()> {
+    let HostAddr::Ip(addr) = target else {
+        return Ok(());
+    };
+    if (bind_addr.is_ipv4() && addr.is_ipv6())
+        || (bind_addr.is_ipv6() && ipv6_only && addr.is_ipv4())
+    {
+        return Err(anyhow!(
+            "{} is not reachable from a socket bound to {}",
+            addr,
+            bind_addr
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses a destination given as `ip`, `ip:port`, `[ipv6]:port`, `host` or
+/// `host:port`, IPv6 link-local addresses taking a scope such as
+/// `fe80::1%eth0`. `default_port` is used when the port is missing.
+pub fn parse_destination(destination: &str, default_port: u16) -> Result<HostAddr> {
+    HostAddr::parse(destination, default_port)
+        .with_context(|| format!("Invalid destination {}", destination))
+}
@@ -0,0 +1,46 @@
+/// Triangular probability density function (TPDF) dither, applied when a
+/// sample is reduced to fewer bits than the capture device provides.
+pub struct Dither {
+    enabled: bool,
+    state: u32,
+}
+
+impl Dither {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            state: 0x9E37_79B9,
+        }
+    }
+
+    /// Quantizes a full scale `i32` sample to `bits` bits, keeping it full
+    /// scale so it can be handed to the codec as is.
+    pub fn quantize(&mut self, sample: i32, bits: u32) -> i32 {
+        if bits >= 32 {
+            return sample;
+        }
+
+        let lsb = 1i64 << (32 - bits);
+        let offset = if self.enabled {
+            // Sum of two uniform values in [0, lsb) is triangular in [0, 2 * lsb),
+            // centering it gives the classic +/- 1 LSB TPDF noise.
+            (self.next() as i64 % lsb) + (self.next() as i64 % lsb) - lsb + lsb / 2
+        } else {
+            lsb / 2
+        };
+        let value = (sample as i64 + offset).clamp(i32::MIN as i64, i32::MAX as i64);
+
+        // Clear the bits below the target resolution so the codec shift is exact
+        ((value as i32) >> (32 - bits)) << (32 - bits)
+    }
+
+    /// xorshift32, plenty for dither noise and free of allocations.
+    fn next(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+}
@@ -1,57 +1,141 @@
+mod destinations;
+mod dither;
+mod packetizer;
+mod sender;
 mod stream;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::protocol::header::MAX_NUM_CHANNELS;
+pub use crate::protocol::header::{BitResolution, SampleRate};
 use crate::utils::errors::RustyVbanError;
+use crate::utils::net::{self, SocketOptions};
+pub use crate::utils::resample::ResampleQuality;
 
+pub use self::destinations::DestinationStatus;
+use self::destinations::{check_reachable, parse_destination, Destinations};
 use self::stream::{VbanEmitterStream, VbanEmitterStreamBuilder};
 
 pub struct EmitterBuilder {
     stream_name: Option<String>,
-    channels: u8,
-    ip_address: Option<String>,
+    channels: u16,
+    samples_per_packet: Option<u16>,
+    destinations: Vec<String>,
     port: u16,
+    bind_address: Option<String>,
+    bind_port: u16,
+    socket_options: SocketOptions,
     device: String,
     device_type: String,
     backend: String,
+    resolution: Resolution,
+    dither: bool,
+    sample_rate: Option<SampleRate>,
+    resample_quality: ResampleQuality,
+    multicast_ttl: u32,
+    multicast_interface: Option<String>,
+    broadcast: bool,
 }
 
 #[allow(dead_code)]
 struct EmitterParams {
     stream_name: String,
-    channels: u8,
-    ip_address: String,
+    channels: u16,
+    samples_per_packet: Option<u16>,
+    destinations: Destinations,
     port: u16,
+    bind_addr: SocketAddr,
+    socket_options: SocketOptions,
     device: String,
     device_type: String,
     backend: String,
+    resolution: Resolution,
+    dither: bool,
+    sample_rate: Option<SampleRate>,
+    resample_quality: ResampleQuality,
+    multicast_ttl: u32,
+    multicast_interface: Option<String>,
+    broadcast: bool,
 }
 
-impl EmitterBuilder {
-    pub fn default() -> Self {
+/// Bit resolution of the samples sent on the wire.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Match the capture device's native sample format.
+    Native,
+    Fixed(BitResolution),
+}
+
+impl From<BitResolution> for Resolution {
+    fn from(bit_resolution: BitResolution) -> Self {
+        Resolution::Fixed(bit_resolution)
+    }
+}
+
+impl Default for EmitterBuilder {
+    fn default() -> Self {
         Self {
             stream_name: None,
             channels: 2,
-            ip_address: None,
+            samples_per_packet: None,
+            destinations: Vec::new(),
             port: 6980,
+            bind_address: None,
+            bind_port: 0,
+            socket_options: SocketOptions::default(),
             device: "default".to_string(),
             device_type: "input".to_string(),
             backend: "default".to_string(),
+            resolution: Resolution::Fixed(BitResolution::Signed16Bit),
+            dither: false,
+            sample_rate: None,
+            resample_quality: ResampleQuality::default(),
+            multicast_ttl: 1,
+            multicast_interface: None,
+            broadcast: false,
         }
     }
+}
 
+impl EmitterBuilder {
     pub fn stream_name<T: Into<String>>(mut self, stream_name: T) -> Self {
         self.stream_name = Some(stream_name.into());
         self
     }
 
-    pub fn channels(mut self, channels: u8) -> Self {
+    /// Number of channels sent on the wire, from 1 to 256. The device is
+    /// opened with this channel count when possible, otherwise its channels
+    /// are remapped to it.
+    pub fn channels(mut self, channels: u16) -> Self {
         self.channels = channels;
         self
     }
 
+    /// Number of samples per channel in each packet. Defaults to the largest
+    /// amount that fits in a packet, lower values reduce latency. Must be at
+    /// least 1, and at most what fits in a packet with the channels and bit
+    /// resolution.
+    pub fn samples_per_packet(mut self, samples_per_packet: u16) -> Self {
+        self.samples_per_packet = Some(samples_per_packet);
+        self
+    }
+
+    /// Sends to this address or hostname, on `port`, along with any other
+    /// destination. Hostnames are resolved again every 30 seconds, following
+    /// hosts whose address changes.
     pub fn ip_address<T: Into<String>>(mut self, ip_address: T) -> Self {
-        self.ip_address = Some(ip_address.into());
+        self.destinations.push(ip_address.into());
+        self
+    }
+
+    /// Also sends every packet to this address, given as `ip`, `ip:port`,
+    /// `[ipv6]:port`, `host` or `host:port`, IPv6 link-local addresses taking
+    /// a scope such as `fe80::1%eth0`. `port` is used when the port is
+    /// missing.
+    pub fn destination<T: Into<String>>(mut self, destination: T) -> Self {
+        self.destinations.push(destination.into());
         self
     }
 
@@ -60,6 +144,56 @@ impl EmitterBuilder {
         self
     }
 
+    /// Address of the local interface packets are sent from, such as
+    /// `192.168.0.10` or `fe80::1%eth0`. Defaults to every interface, over
+    /// IPv6 and IPv4 when a destination only has an IPv6 address or is given
+    /// by hostname.
+    pub fn bind_address<T: Into<String>>(mut self, bind_address: T) -> Self {
+        self.bind_address = Some(bind_address.into());
+        self
+    }
+
+    /// Local port packets are sent from. Defaults to a free port picked by
+    /// the system.
+    pub fn bind_port(mut self, bind_port: u16) -> Self {
+        self.bind_port = bind_port;
+        self
+    }
+
+    /// Lets other sockets bind the same local address and port, with
+    /// `SO_REUSEADDR`.
+    pub fn reuse_address(mut self, reuse_address: bool) -> Self {
+        self.socket_options.reuse_address = reuse_address;
+        self
+    }
+
+    /// Lets other processes send from the same local port, with
+    /// `SO_REUSEPORT`. Only available on Unix.
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.socket_options.reuse_port = reuse_port;
+        self
+    }
+
+    /// Size of the socket send buffer, in bytes.
+    pub fn send_buffer_size(mut self, send_buffer_size: usize) -> Self {
+        self.socket_options.send_buffer_size = Some(send_buffer_size);
+        self
+    }
+
+    /// Marks packets with a DSCP class from 0 to 63, such as 46 for expedited
+    /// forwarding, so the network can prioritize them.
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.socket_options.set_dscp(dscp);
+        self
+    }
+
+    /// Marks packets with a raw type of service byte, the DSCP class in its
+    /// upper six bits and ECN in the lower two.
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.socket_options.tos = Some(tos.into());
+        self
+    }
+
     pub fn device<T: Into<String>>(mut self, device: T) -> Self {
         self.device = device.into();
         self
@@ -75,27 +209,114 @@ impl EmitterBuilder {
         self
     }
 
+    pub fn bit_resolution<T: Into<Resolution>>(mut self, resolution: T) -> Self {
+        self.resolution = resolution.into();
+        self
+    }
+
+    /// Applies TPDF dither when samples are reduced to a lower bit resolution.
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Sample rate sent on the wire. Defaults to the capture device rate,
+    /// audio is resampled when the device runs at a different rate.
+    pub fn sample_rate(mut self, sample_rate: SampleRate) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// Interpolation used when resampling to the target sample rate.
+    pub fn resample_quality(mut self, resample_quality: ResampleQuality) -> Self {
+        self.resample_quality = resample_quality;
+        self
+    }
+
+    /// Number of router hops packets sent to a multicast group survive.
+    pub fn multicast_ttl(mut self, multicast_ttl: u32) -> Self {
+        self.multicast_ttl = multicast_ttl;
+        self
+    }
+
+    /// Local interface packets sent to a multicast group go out of, given by
+    /// address for IPv4 groups and by name or index such as `eth0` for IPv6
+    /// ones. Defaults to the one picked by the routing table.
+    pub fn multicast_interface<T: Into<String>>(mut self, multicast_interface: T) -> Self {
+        self.multicast_interface = Some(multicast_interface.into());
+        self
+    }
+
+    /// Allows sending to a broadcast address such as `192.168.0.255`.
+    pub fn broadcast(mut self, broadcast: bool) -> Self {
+        self.broadcast = broadcast;
+        self
+    }
+
     pub fn build(self) -> Result<Emitter> {
         let stream_name = self.stream_name.context("Stream name is required")?;
-        let ip_address = self.ip_address.context("IP address is required")?;
+        if self.destinations.is_empty() {
+            return Err(anyhow!("IP address is required"));
+        }
+        let targets = self
+            .destinations
+            .iter()
+            .map(|destination| parse_destination(destination, self.port))
+            .collect::<Result<Vec<_>>>()?;
+        let destinations = Destinations::new(&targets)?;
+        let bind_addr = match self.bind_address.as_ref() {
+            Some(address) => {
+                net::parse_socket_addr(address, self.bind_port).context("Invalid bind address")?
+            }
+            None => default_bind_addr(&destinations, self.bind_port),
+        };
+        for target in &targets {
+            check_reachable(bind_addr, self.socket_options.ipv6_only, target)?;
+        }
+        if !(1..=MAX_NUM_CHANNELS as u16).contains(&self.channels) {
+            return Err(anyhow!(
+                "Channels must be between 1 and {}",
+                MAX_NUM_CHANNELS
+            ));
+        }
 
         let stream = VbanEmitterStreamBuilder::default()
             .device_name(&self.device)
             .device_type(&self.device_type)
             .host_name(&self.backend)
-            .ip_address(&ip_address)
-            .port(self.port)
+            .destinations(destinations.clone())
+            .bind_addr(bind_addr)
+            .socket_options(self.socket_options.clone())
             .stream_name(&stream_name)
+            .channels(self.channels)
+            .samples_per_packet(self.samples_per_packet)
+            .resolution(self.resolution)
+            .dither(self.dither)
+            .sample_rate(self.sample_rate)
+            .resample_quality(self.resample_quality)
+            .multicast_ttl(self.multicast_ttl)
+            .multicast_interface(self.multicast_interface.clone())
+            .broadcast(self.broadcast)
             .build()?;
 
         let params = EmitterParams {
             stream_name,
             channels: self.channels,
-            ip_address,
+            samples_per_packet: self.samples_per_packet,
+            destinations,
             port: self.port,
+            bind_addr,
+            socket_options: self.socket_options,
             device: self.device,
             device_type: self.device_type,
             backend: self.backend,
+            resolution: self.resolution,
+            dither: self.dither,
+            sample_rate: self.sample_rate,
+            resample_quality: self.resample_quality,
+            multicast_ttl: self.multicast_ttl,
+            multicast_interface: self.multicast_interface,
+            broadcast: self.broadcast,
         };
 
         Ok(Emitter { stream, params })
@@ -107,16 +328,11 @@ pub struct Emitter {
     params: EmitterParams,
 }
 
+#[derive(Default)]
 pub struct EmitterOptions {
     pub retry: bool,
 }
 
-impl Default for EmitterOptions {
-    fn default() -> Self {
-        Self { retry: false }
-    }
-}
-
 impl Emitter {
     pub fn run(mut self, options: EmitterOptions) -> Result<Self, RustyVbanError> {
         self.play()?;
@@ -148,6 +364,37 @@ impl Emitter {
         self.stream.should_run(&self.params.device)
     }
 
+    /// Starts sending to another address, given as `ip`, `ip:port`,
+    /// `[ipv6]:port`, `host` or `host:port`. IPv6 addresses are only
+    /// reachable when the emitter was built with an IPv6 destination or bind
+    /// address, and fail otherwise. Returns false when it was already a
+    /// destination.
+    pub fn add_destination(&self, destination: &str) -> Result<bool> {
+        let target = parse_destination(destination, self.params.port)?;
+        check_reachable(
+            self.params.bind_addr,
+            self.params.socket_options.ipv6_only,
+            &target,
+        )?;
+        let added = self.params.destinations.add(target)?;
+        self.stream.follow_hosts();
+
+        Ok(added)
+    }
+
+    /// Stops sending to an address, given as it was added. Returns false when
+    /// it was not a destination.
+    pub fn remove_destination(&self, destination: &str) -> Result<bool> {
+        let target = parse_destination(destination, self.params.port)?;
+
+        Ok(self.params.destinations.remove(&target))
+    }
+
+    /// Packets sent and send errors of every destination.
+    pub fn destinations(&self) -> Vec<DestinationStatus> {
+        self.params.destinations.status()
+    }
+
     pub fn rebuild(self) -> Result<Self> {
         let Self { stream, params } = self;
 
@@ -157,11 +404,32 @@ impl Emitter {
             .device_name(&params.device)
             .device_type(&params.device_type)
             .host_name(&params.backend)
-            .ip_address(&params.ip_address)
-            .port(params.port)
+            .destinations(params.destinations.clone())
+            .bind_addr(params.bind_addr)
+            .socket_options(params.socket_options.clone())
             .stream_name(&params.stream_name)
+            .channels(params.channels)
+            .samples_per_packet(params.samples_per_packet)
+            .resolution(params.resolution)
+            .dither(params.dither)
+            .sample_rate(params.sample_rate)
+            .resample_quality(params.resample_quality)
+            .multicast_ttl(params.multicast_ttl)
+            .multicast_interface(params.multicast_interface.clone())
+            .broadcast(params.broadcast)
             .build()?;
 
         Ok(Self { stream, params })
     }
 }
+
+/// Binds every interface, dual-stack when a destination only has IPv6
+/// addresses or is given by hostname, which may later resolve to IPv6 ones,
+/// so IPv4 destinations stay reachable.
+fn default_bind_addr(destinations: &Destinations, port: u16) -> SocketAddr {
+    if destinations.has_hosts() || destinations.requires_ipv6() {
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))
+    } else {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+    }
+}
@@ -0,0 +1,209 @@
+use crate::protocol::{
+    codec::{self, CodecSample},
+    header::{BitResolution, Header, HEADER_SIZE, MAX_NUM_SAMPLES},
+    packet::{Error, MAX_PACKET_SIZE},
+};
+use crate::utils::log;
+
+use super::dither::Dither;
+
+/// Largest number of frames a packet can hold for the given channel count
+/// and bit resolution without exceeding `MAX_PACKET_SIZE`.
+pub fn max_frames_per_packet(channels: usize, bit_resolution: BitResolution) -> usize {
+    let payload_bits = (MAX_PACKET_SIZE - HEADER_SIZE) * 8;
+    MAX_NUM_SAMPLES.min(payload_bits / (channels * bit_resolution.bits()))
+}
+
+/// Groups interleaved frames into packets of a fixed number of frames,
+/// carrying incomplete packets over to the next callback so a frame is never
+/// split across packets.
+///
+/// Every buffer is allocated up front, so pushing frames and encoding packets
+/// never touches the heap.
+pub struct Packetizer {
+    header: Header,
+    channels: usize,
+    frames_per_packet: usize,
+    dither: Dither,
+    samples: Vec<f64>,
+    quantized: Vec<i32>,
+    buffer: [u8; MAX_PACKET_SIZE],
+}
+
+impl Packetizer {
+    pub fn new(mut header: Header, frames_per_packet: usize, dither: Dither) -> Self {
+        let channels = header.num_channels() as usize;
+        header.set_num_samples(frames_per_packet as u16);
+
+        Self {
+            header,
+            channels,
+            frames_per_packet,
+            dither,
+            samples: Vec::with_capacity(frames_per_packet * channels),
+            quantized: vec![0; frames_per_packet * channels],
+            buffer: [0; MAX_PACKET_SIZE],
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn frames_per_packet(&self) -> usize {
+        self.frames_per_packet
+    }
+
+    /// Appends one frame of `channels` samples, returning the encoded packet
+    /// once it holds `frames_per_packet` frames.
+    pub fn push_frame(&mut self, frame: &[f64]) -> Option<&[u8]> {
+        self.samples.extend_from_slice(&frame[..self.channels]);
+        if self.samples.len() < self.frames_per_packet * self.channels {
+            return None;
+        }
+
+        let encoded = self.encode();
+        self.samples.clear();
+        self.header
+            .set_frame_number(self.header.frame_number().wrapping_add(1));
+
+        match encoded {
+            Ok(len) => Some(&self.buffer[..len]),
+            Err(e) => {
+                log::error(&format!("error encoding data: {}", e));
+                None
+            }
+        }
+    }
+
+    /// Encodes the header and pending samples into the packet buffer,
+    /// returning the packet length.
+    fn encode(&mut self) -> Result<usize, Error> {
+        let bit_resolution = self.header.bit_resolution();
+        let bits = bit_resolution.bits() as u32;
+        let header_len = self.header.encode_into(&mut self.buffer)?;
+        let payload = &mut self.buffer[header_len..];
+
+        let data_len = match bit_resolution {
+            BitResolution::Float32Bit | BitResolution::Float64Bit => {
+                codec::encode(bit_resolution, &self.samples, payload)?
+            }
+            _ => {
+                for (quantized, sample) in self.quantized.iter_mut().zip(&self.samples) {
+                    *quantized = self.dither.quantize(i32::from_f64(*sample), bits);
+                }
+                codec::encode(bit_resolution, &self.quantized, payload)?
+            }
+        };
+
+        Ok(header_len + data_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::packet::PacketRef;
+
+    const RESOLUTIONS: [BitResolution; 8] = [
+        BitResolution::Unsigned8Bit,
+        BitResolution::Signed16Bit,
+        BitResolution::Signed24Bit,
+        BitResolution::Signed32Bit,
+        BitResolution::Float32Bit,
+        BitResolution::Float64Bit,
+        BitResolution::Signed12Bit,
+        BitResolution::Signed10Bit,
+    ];
+
+    const CHANNELS: [usize; 4] = [1, 2, 8, 256];
+
+    fn packetizer(
+        channels: usize,
+        bit_resolution: BitResolution,
+        frames_per_packet: usize,
+    ) -> Packetizer {
+        let mut header = Header::new("Stream1");
+        header.set_bit_resolution(bit_resolution);
+        header.set_num_channels(channels as u16);
+        Packetizer::new(header, frames_per_packet, Dither::new(false))
+    }
+
+    /// Every channel of a frame carries the frame index, exactly representable
+    /// in 8 bits.
+    fn frame_value(index: usize) -> f64 {
+        (index % 128) as f64 / 128.0 - 0.5
+    }
+
+    #[test]
+    fn max_frames_fill_but_never_exceed_a_packet() {
+        for channels in CHANNELS {
+            for bit_resolution in RESOLUTIONS {
+                let max_frames = max_frames_per_packet(channels, bit_resolution);
+                let packet_len =
+                    |frames| HEADER_SIZE + codec::encoded_len(bit_resolution, frames * channels);
+                assert!(max_frames <= MAX_NUM_SAMPLES);
+                assert!(
+                    packet_len(max_frames) <= MAX_PACKET_SIZE,
+                    "{} channels of {:?}",
+                    channels,
+                    bit_resolution
+                );
+                if max_frames < MAX_NUM_SAMPLES {
+                    // One more frame would not fit
+                    assert!(
+                        packet_len(max_frames + 1) > MAX_PACKET_SIZE,
+                        "{} channels of {:?}",
+                        channels,
+                        bit_resolution
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn packets_hold_whole_frames() {
+        for channels in CHANNELS {
+            for bit_resolution in RESOLUTIONS {
+                let max_frames = max_frames_per_packet(channels, bit_resolution);
+                // 256 channels of 64 bit floats do not fit in any packet
+                if max_frames == 0 {
+                    assert_eq!((channels, bit_resolution.bits()), (256, 64));
+                    continue;
+                }
+
+                for frames_per_packet in [1, max_frames.div_ceil(3), max_frames] {
+                    let mut packetizer = packetizer(channels, bit_resolution, frames_per_packet);
+                    let mut packets = 0;
+                    for index in 0..frames_per_packet * 3 - 1 {
+                        let frame = vec![frame_value(index); channels];
+                        let Some(bytes) = packetizer.push_frame(&frame) else {
+                            continue;
+                        };
+                        assert_eq!((index + 1) % frames_per_packet, 0);
+                        assert!(bytes.len() <= MAX_PACKET_SIZE);
+
+                        let packet = PacketRef::try_from(bytes).unwrap();
+                        assert_eq!(packet.header().num_channels() as usize, channels);
+                        assert_eq!(packet.header().num_samples() as usize, frames_per_packet);
+                        let samples = packet.samples::<f64>().collect::<Vec<_>>();
+                        assert_eq!(samples.len(), frames_per_packet * channels);
+                        for (frame_index, frame) in samples.chunks_exact(channels).enumerate() {
+                            let expected = frame_value(packets * frames_per_packet + frame_index);
+                            assert!(
+                                frame.iter().all(|sample| *sample == expected),
+                                "{} channels of {:?}, {} frames per packet",
+                                channels,
+                                bit_resolution,
+                                frames_per_packet
+                            );
+                        }
+                        packets += 1;
+                    }
+                    assert_eq!(packets, 2);
+                }
+            }
+        }
+    }
+}
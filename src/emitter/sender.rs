@@ -0,0 +1,113 @@
+use std::{
+    mem::MaybeUninit,
+    net::UdpSocket,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ringbuf::{Consumer, HeapRb, Producer, SharedRb};
+
+use crate::utils::resample::Resampler;
+
+use super::destinations::Destinations;
+use super::packetizer::Packetizer;
+
+pub type VbanFrameProducer = Producer<f64, Arc<SharedRb<f64, Vec<MaybeUninit<f64>>>>>;
+pub type VbanFrameConsumer = Consumer<f64, Arc<SharedRb<f64, Vec<MaybeUninit<f64>>>>>;
+
+/// Audio queued above this duration is sent without pacing, so a capture
+/// clock running slightly fast never lets the queue grow unbounded.
+const MAX_QUEUED_SECS: f64 = 0.1;
+
+/// Creates the ring buffer between the capture callback and the sender
+/// thread, large enough to hold one second of audio.
+pub fn frame_ring_buffer(
+    sample_rate: u32,
+    channels: usize,
+) -> (VbanFrameProducer, VbanFrameConsumer) {
+    HeapRb::<f64>::new(sample_rate as usize * channels).split()
+}
+
+/// Thread resampling captured frames to the stream rate, packetizing them and
+/// sending them at the pace of the stream rate, keeping network I/O out of
+/// the audio callback.
+pub struct Sender {
+    handle: Option<JoinHandle<()>>,
+    running: Arc<AtomicBool>,
+}
+
+impl Sender {
+    pub fn spawn(
+        mut packetizer: Packetizer,
+        mut resampler: Resampler,
+        mut consumer: VbanFrameConsumer,
+        socket: UdpSocket,
+        destinations: Destinations,
+    ) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+
+        let ipv6 = socket.local_addr().is_ok_and(|addr| addr.is_ipv6());
+
+        let handle = thread::spawn(move || {
+            let channels = packetizer.channels();
+            let frames_per_packet = packetizer.frames_per_packet();
+            let input_rate = resampler.input_rate() as f64;
+            let output_rate = resampler.output_rate() as f64;
+            // Captured frames needed to fill one packet at the output rate
+            let input_frames =
+                (frames_per_packet as f64 * input_rate / output_rate).ceil() as usize;
+            let packet_duration = Duration::from_secs_f64(frames_per_packet as f64 / output_rate);
+            let max_queued = (MAX_QUEUED_SECS * input_rate) as usize * channels;
+            let mut frame = vec![0.0; channels];
+            let mut next_send = Instant::now();
+
+            while running_clone.load(Ordering::Relaxed) {
+                if consumer.len() < input_frames * channels {
+                    thread::sleep(packet_duration / 4);
+                    continue;
+                }
+
+                for _ in 0..input_frames {
+                    consumer.pop_slice(&mut frame);
+                    let queued = consumer.len();
+                    resampler.process(frame.iter().copied(), |frame| {
+                        let Some(packet) = packetizer.push_frame(frame) else {
+                            return;
+                        };
+
+                        let now = Instant::now();
+                        if queued >= max_queued || next_send + packet_duration < now {
+                            // Either catching up on a backlog or resuming after
+                            // running out of data, restart the schedule from now
+                            next_send = now;
+                        } else if now < next_send {
+                            thread::sleep(next_send - now);
+                        }
+
+                        destinations.send(&socket, ipv6, packet);
+                        next_send += packet_duration;
+                    });
+                }
+            }
+        });
+
+        Self {
+            handle: Some(handle),
+            running,
+        }
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
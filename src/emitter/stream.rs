@@ -8,42 +8,49 @@ use std::{
 
 use cpal::{
     traits::{DeviceTrait, StreamTrait},
-    PauseStreamError, PlayStreamError, Sample, SampleFormat, SizedSample,
+    PauseStreamError, PlayStreamError, Sample, SampleFormat, SizedSample, StreamConfig,
 };
 
+use socket2::SockRef;
+
+use crate::protocol::header::{BitResolution, Header, SampleRate};
+use crate::utils::cpal::StreamStatus;
 use crate::utils::cpal::{Device, Host};
 use crate::utils::log;
+use crate::utils::net::{self, Interface, SocketOptions};
+use crate::utils::resample::{ResampleQuality, Resampler};
+use crate::utils::resolve::Resolver;
 use crate::utils::{self, cpal::Status};
-use crate::{
-    protocol::header::{Header, MAX_NUM_SAMPLES},
-    utils::cpal::StreamStatus,
-};
+
+use super::destinations::Destinations;
+use super::dither::Dither;
+use super::packetizer::{self, Packetizer};
+use super::sender::{frame_ring_buffer, Sender, VbanFrameProducer};
+use super::Resolution;
 
 pub struct StreamWrapper(Arc<Mutex<cpal::Stream>>);
 
 unsafe impl Send for StreamWrapper {}
 unsafe impl Sync for StreamWrapper {}
 
+#[derive(Default)]
 pub struct VbanEmitterStreamBuilder {
     device_name: Option<String>,
     device_type: Option<String>,
     host_name: Option<String>,
-    ip_address: Option<String>,
-    port: Option<u16>,
+    destinations: Option<Destinations>,
+    bind_addr: Option<SocketAddr>,
+    socket_options: Option<SocketOptions>,
     stream_name: Option<String>,
-}
-
-impl Default for VbanEmitterStreamBuilder {
-    fn default() -> Self {
-        Self {
-            device_name: None,
-            device_type: None,
-            host_name: None,
-            ip_address: None,
-            port: None,
-            stream_name: None,
-        }
-    }
+    channels: Option<u16>,
+    samples_per_packet: Option<u16>,
+    resolution: Option<Resolution>,
+    dither: Option<bool>,
+    sample_rate: Option<SampleRate>,
+    resample_quality: Option<ResampleQuality>,
+    multicast_ttl: Option<u32>,
+    multicast_interface: Option<String>,
+    broadcast: Option<bool>,
 }
 
 impl VbanEmitterStreamBuilder {
@@ -62,13 +69,18 @@ impl VbanEmitterStreamBuilder {
         self
     }
 
-    pub fn ip_address(mut self, ip_address: &str) -> Self {
-        self.ip_address = Some(ip_address.to_string());
+    pub fn destinations(mut self, destinations: Destinations) -> Self {
+        self.destinations = Some(destinations);
         self
     }
 
-    pub fn port(mut self, port: u16) -> Self {
-        self.port = Some(port);
+    pub fn bind_addr(mut self, bind_addr: SocketAddr) -> Self {
+        self.bind_addr = Some(bind_addr);
+        self
+    }
+
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = Some(socket_options);
         self
     }
 
@@ -77,13 +89,74 @@ impl VbanEmitterStreamBuilder {
         self
     }
 
+    pub fn channels(mut self, channels: u16) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn samples_per_packet(mut self, samples_per_packet: Option<u16>) -> Self {
+        self.samples_per_packet = samples_per_packet;
+        self
+    }
+
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = Some(resolution);
+        self
+    }
+
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.dither = Some(dither);
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: Option<SampleRate>) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn resample_quality(mut self, resample_quality: ResampleQuality) -> Self {
+        self.resample_quality = Some(resample_quality);
+        self
+    }
+
+    pub fn multicast_ttl(mut self, multicast_ttl: u32) -> Self {
+        self.multicast_ttl = Some(multicast_ttl);
+        self
+    }
+
+    pub fn multicast_interface(mut self, multicast_interface: Option<String>) -> Self {
+        self.multicast_interface = multicast_interface;
+        self
+    }
+
+    pub fn broadcast(mut self, broadcast: bool) -> Self {
+        self.broadcast = Some(broadcast);
+        self
+    }
+
     pub fn build(self) -> Result<VbanEmitterStream> {
         let device_name = self.device_name.context("device name is required")?;
         let device_type = self.device_type.context("device type is required")?;
         let host_name = self.host_name.context("host name is required")?;
-        let ip_address = self.ip_address.context("ip address is required")?;
-        let port = self.port.context("port is required")?;
+        let destinations = self.destinations.context("destinations are required")?;
+        let bind_addr = self.bind_addr.context("bind address is required")?;
+        let socket_options = self.socket_options.context("socket options are required")?;
         let stream_name = self.stream_name.context("stream name is required")?;
+        let channels = self.channels.context("channels is required")?;
+        let samples_per_packet = self.samples_per_packet;
+        let resolution = self.resolution.context("resolution is required")?;
+        let dither = self.dither.context("dither is required")?;
+        let sample_rate = self.sample_rate;
+        let resample_quality = self
+            .resample_quality
+            .context("resample quality is required")?;
+        let multicast_ttl = self.multicast_ttl.context("multicast ttl is required")?;
+        let multicast_interface = self
+            .multicast_interface
+            .map(|interface| Interface::parse(&interface))
+            .transpose()
+            .context("invalid multicast interface")?;
+        let broadcast = self.broadcast.context("broadcast is required")?;
 
         let host = Arc::new(utils::cpal::host_by_name(&host_name)?);
         let device = Arc::new(match device_type.as_str() {
@@ -95,30 +168,93 @@ impl VbanEmitterStreamBuilder {
                 .ok_or(anyhow!("no input device available"))?,
             _ => return Err(anyhow!("invalid device type")),
         });
-        let addrs = (1..=10)
-            .map(|i| SocketAddr::from(([0, 0, 0, 0], port + i)))
-            .collect::<Vec<SocketAddr>>();
-        let header = Header::new(&stream_name);
-        let target = SocketAddr::new(ip_address.parse()?, port);
+        let config = device.input_config_with_channels(channels)?;
+        let sample_format = config.sample_format();
+        let bit_resolution = match resolution {
+            Resolution::Native => native_bit_resolution(sample_format),
+            Resolution::Fixed(bit_resolution) => bit_resolution,
+        };
+        let max_frames = packetizer::max_frames_per_packet(channels as usize, bit_resolution);
+        if max_frames == 0 {
+            return Err(anyhow!(
+                "{} channels of {:?} do not fit in a single packet",
+                channels,
+                bit_resolution
+            ));
+        }
+        let frames_per_packet = match samples_per_packet {
+            Some(samples) if !(1..=max_frames).contains(&(samples as usize)) => {
+                return Err(anyhow!(
+                    "samples per packet must be between 1 and {} with {} channels of {:?}",
+                    max_frames,
+                    channels,
+                    bit_resolution
+                ))
+            }
+            Some(samples) => samples as usize,
+            None => max_frames,
+        };
+        let capture_rate = config.sample_rate().0;
+        let sample_rate = match sample_rate {
+            Some(sample_rate) => sample_rate,
+            None => SampleRate::from_hz(capture_rate).ok_or(anyhow!(
+                "capture rate of {} Hz is not a VBAN sample rate, set a target sample rate",
+                capture_rate
+            ))?,
+        };
+        let mut header = Header::new(&stream_name);
+        header.set_sample_rate(sample_rate);
+        header.set_bit_resolution(bit_resolution);
+        header.set_num_channels(channels);
+        let dither = Dither::new(
+            dither
+                && !is_float(bit_resolution)
+                && (bit_resolution.bits() as u32) < source_bits(sample_format),
+        );
+        let packetizer = Packetizer::new(header, frames_per_packet, dither);
+        let socket = net::bind_udp(bind_addr, &socket_options)?;
+        socket.set_broadcast(broadcast)?;
+        set_multicast_options(&socket, multicast_ttl, multicast_interface)?;
         let status = Arc::new(Mutex::new(Status::Ok));
-
+        let resampler = Resampler::new(
+            resample_quality,
+            capture_rate,
+            sample_rate.hz(),
+            channels as usize,
+        );
+        let (producer, consumer) = frame_ring_buffer(capture_rate, channels as usize);
+        let sender = Sender::spawn(
+            packetizer,
+            resampler,
+            consumer,
+            socket,
+            destinations.clone(),
+        );
+
+        #[allow(clippy::arc_with_non_send_sync)]
         let stream = StreamWrapper(Arc::new(Mutex::new(build_stream_for_sample_format(
-            device.default_input_config()?.sample_format(),
+            sample_format,
             StreamParams {
                 device: device.clone(),
-                header,
-                addrs,
-                target,
+                config: config.into(),
+                channels: channels as usize,
+                producer,
                 status: status.clone(),
             },
         )?)));
 
-        Ok(VbanEmitterStream {
+        let stream = VbanEmitterStream {
             host,
             device,
             stream,
+            _sender: sender,
+            destinations,
+            resolver: Mutex::new(None),
             status,
-        })
+        };
+        stream.follow_hosts();
+
+        Ok(stream)
     }
 }
 
@@ -126,6 +262,9 @@ pub struct VbanEmitterStream {
     host: Arc<cpal::Host>,
     device: Arc<cpal::Device>,
     stream: StreamWrapper,
+    _sender: Sender,
+    destinations: Destinations,
+    resolver: Mutex<Option<Resolver>>,
     status: StreamStatus,
 }
 
@@ -142,6 +281,16 @@ impl VbanEmitterStream {
         Ok(())
     }
 
+    /// Starts resolving the destinations given by hostname again
+    /// periodically, once there is one.
+    pub fn follow_hosts(&self) {
+        let mut resolver = self.resolver.lock().unwrap();
+        if resolver.is_none() && self.destinations.has_hosts() {
+            let destinations = self.destinations.clone();
+            *resolver = Some(Resolver::spawn(move || destinations.refresh()));
+        }
+    }
+
     pub fn should_run(&self, device_name: &str) -> bool {
         if !self.running()
             || (device_name == "default" && !self.device.is_default_input(&self.host))
@@ -160,11 +309,67 @@ impl VbanEmitterStream {
     }
 }
 
+/// Sets the hop limit and outgoing interface of packets sent to multicast
+/// destinations.
+fn set_multicast_options(socket: &UdpSocket, ttl: u32, interface: Option<Interface>) -> Result<()> {
+    let socket = SockRef::from(socket);
+    let ipv6 = socket.local_addr()?.is_ipv6();
+    if ipv6 {
+        socket.set_multicast_hops_v6(ttl)?;
+        // Groups of IPv4 destinations sent to from a dual-stack socket, on
+        // the systems applying IPv4 options to IPv6 sockets
+        let _ = socket.set_multicast_ttl_v4(ttl);
+    } else {
+        socket.set_multicast_ttl_v4(ttl)?;
+    }
+
+    match interface {
+        Some(Interface::Addr(addr)) => socket.set_multicast_if_v4(&addr)?,
+        Some(Interface::Index(index)) if ipv6 => socket.set_multicast_if_v6(index)?,
+        Some(Interface::Index(_)) => {
+            return Err(anyhow!(
+                "IPv4 multicast interfaces are given by address, not by name"
+            ))
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Picks the VBAN bit resolution closest to a cpal sample format.
+fn native_bit_resolution(sample_format: SampleFormat) -> BitResolution {
+    match sample_format {
+        SampleFormat::I8 | SampleFormat::U8 => BitResolution::Unsigned8Bit,
+        SampleFormat::I16 | SampleFormat::U16 => BitResolution::Signed16Bit,
+        SampleFormat::F32 => BitResolution::Float32Bit,
+        SampleFormat::F64 => BitResolution::Float64Bit,
+        _ => BitResolution::Signed32Bit,
+    }
+}
+
+/// Effective precision of a cpal sample format, in bits.
+fn source_bits(sample_format: SampleFormat) -> u32 {
+    match sample_format {
+        SampleFormat::I8 | SampleFormat::U8 => 8,
+        SampleFormat::I16 | SampleFormat::U16 => 16,
+        SampleFormat::F32 => 24,
+        _ => 32,
+    }
+}
+
+fn is_float(bit_resolution: BitResolution) -> bool {
+    matches!(
+        bit_resolution,
+        BitResolution::Float32Bit | BitResolution::Float64Bit
+    )
+}
+
 struct StreamParams {
     device: Arc<cpal::Device>,
-    header: Header,
-    addrs: Vec<SocketAddr>,
-    target: SocketAddr,
+    config: StreamConfig,
+    channels: usize,
+    producer: VbanFrameProducer,
     status: StreamStatus,
 }
 
@@ -189,27 +394,25 @@ fn build_stream_for_sample_format(
 
 fn build_stream<T>(params: StreamParams) -> Result<cpal::Stream>
 where
-    T: SizedSample + ToSample<i16> + Send + 'static,
+    T: SizedSample + ToSample<f64> + Send + 'static,
 {
     let StreamParams {
         device,
-        header,
-        addrs,
-        target,
+        config,
+        channels,
+        mut producer,
         status,
     } = params;
-    let config = device.default_input_config()?;
+    let device_channels = config.channels as usize;
     let err_fn = move |error| {
         log::error(&format!("an error occurred on stream: {}", error));
         *status.lock().unwrap() = Status::Err(error);
     };
-    let mut frame_count = 0;
-
-    let socket = UdpSocket::bind(&addrs[..])?;
+    let mut frame = vec![0.0; channels];
 
     let stream = device.build_input_stream(
-        &config.into(),
-        move |data: &[T], _: &_| write_data::<T>(data, header, &socket, &target, &mut frame_count),
+        &config,
+        move |data: &[T], _: &_| write_data::<T>(data, device_channels, &mut frame, &mut producer),
         err_fn,
         None,
     )?;
@@ -217,39 +420,44 @@ where
     Ok(stream)
 }
 
+/// Queues the captured frames for the sender thread. Frames that do not fit
+/// in the queue are dropped whole rather than blocking the callback.
 fn write_data<T>(
     input: &[T],
-    mut header: Header,
-    socket: &UdpSocket,
-    addr: &SocketAddr,
-    frame_count: &mut u32,
+    device_channels: usize,
+    frame: &mut [f64],
+    producer: &mut VbanFrameProducer,
 ) where
-    T: Sample + ToSample<i16>,
+    T: Sample + ToSample<f64>,
 {
-    let total_samples = input.len();
-    let mut chunks_amount = total_samples / MAX_NUM_SAMPLES;
-    if total_samples % MAX_NUM_SAMPLES > 0 {
-        chunks_amount += 1;
-    }
-    let chunk_num_samples = total_samples / chunks_amount;
-
-    for samples in input.chunks(chunk_num_samples) {
-        let mut buffer = Vec::new();
-
-        header.set_num_samples((samples.len() / header.num_channels() as usize - 1) as u8);
-        header.set_frame_number(*frame_count);
-        let header: [u8; 28] = header.into();
-        let data = samples
-            .iter()
-            .flat_map(|s| s.to_sample::<i16>().to_le_bytes())
-            .collect::<Vec<u8>>();
-
-        buffer.extend_from_slice(&header);
-        buffer.extend_from_slice(&data);
-        if let Err(e) = socket.send_to(&buffer[..buffer.len()], addr) {
-            log::error(&format!("error sending data: {}", e));
+    for device_frame in input.chunks_exact(device_channels) {
+        if producer.free_len() < frame.len() {
+            break;
         }
+        remap(device_frame, frame);
+        producer.push_slice(frame);
+    }
+}
+
+/// Converts a device frame to the stream channel count. Extra device
+/// channels are dropped, missing ones repeat the device channels in order
+/// and a mono stream gets the average of every device channel.
+fn remap<T>(input: &[T], output: &mut [f64])
+where
+    T: Sample + ToSample<f64>,
+{
+    let (from, to) = (input.len(), output.len());
 
-        *frame_count += 1;
+    if from == to {
+        for (output, input) in output.iter_mut().zip(input) {
+            *output = input.to_sample::<f64>();
+        }
+    } else if to == 1 {
+        let sum = input.iter().map(|s| s.to_sample::<f64>()).sum::<f64>();
+        output[0] = sum / from as f64;
+    } else {
+        for (channel, output) in output.iter_mut().enumerate() {
+            *output = input[channel % from].to_sample::<f64>();
+        }
     }
 }
@@ -1,4 +1,4 @@
 pub mod emitter;
-mod protocol;
+pub mod protocol;
 pub mod receptor;
 pub mod utils;
@@ -0,0 +1,503 @@
+use std::marker::PhantomData;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::header::BitResolution;
+use super::packet::Error;
+
+/// A sample type the codec can encode from and decode into.
+///
+/// Integer samples use the full `i32` range, float samples the `-1.0..1.0`
+/// range, so converting between formats never needs to know the source
+/// bit resolution.
+pub trait CodecSample: Copy {
+    /// Converts the sample into a signed integer of `bits` bits.
+    fn to_int(self, bits: u32) -> i32;
+
+    /// Builds a sample from a signed integer of `bits` bits.
+    fn from_int(value: i32, bits: u32) -> Self;
+
+    fn to_f64(self) -> f64;
+
+    fn from_f64(value: f64) -> Self;
+}
+
+impl CodecSample for f32 {
+    fn to_int(self, bits: u32) -> i32 {
+        let scale = (1i64 << (bits - 1)) as f64;
+        (self as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+    }
+
+    fn from_int(value: i32, bits: u32) -> Self {
+        (value as f64 / (1i64 << (bits - 1)) as f64) as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl CodecSample for f64 {
+    fn to_int(self, bits: u32) -> i32 {
+        let scale = (1i64 << (bits - 1)) as f64;
+        (self * scale).round().clamp(-scale, scale - 1.0) as i32
+    }
+
+    fn from_int(value: i32, bits: u32) -> Self {
+        value as f64 / (1i64 << (bits - 1)) as f64
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+impl CodecSample for i32 {
+    fn to_int(self, bits: u32) -> i32 {
+        self >> (32 - bits)
+    }
+
+    fn from_int(value: i32, bits: u32) -> Self {
+        ((value as u32) << (32 - bits)) as i32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64 / 2_147_483_648.0
+    }
+
+    fn from_f64(value: f64) -> Self {
+        (value * 2_147_483_648.0)
+            .round()
+            .clamp(i32::MIN as f64, i32::MAX as f64) as i32
+    }
+}
+
+/// Number of bytes needed to encode `num_samples` interleaved samples.
+pub fn encoded_len(bit_resolution: BitResolution, num_samples: usize) -> usize {
+    (num_samples * bit_resolution.bits()).div_ceil(8)
+}
+
+/// Number of whole samples stored in `len` bytes.
+pub fn decoded_len(bit_resolution: BitResolution, len: usize) -> usize {
+    len * 8 / bit_resolution.bits()
+}
+
+/// Encodes interleaved samples into `output`, returning the number of bytes
+/// written.
+pub fn encode<S: CodecSample>(
+    bit_resolution: BitResolution,
+    input: &[S],
+    output: &mut [u8],
+) -> Result<usize, Error> {
+    let len = encoded_len(bit_resolution, input.len());
+    if output.len() < len {
+        return Err(Error::BufferTooShort {
+            expected: len,
+            actual: output.len(),
+        });
+    }
+    let output = &mut output[..len];
+
+    match bit_resolution {
+        BitResolution::Unsigned8Bit => {
+            for (sample, byte) in input.iter().zip(output.iter_mut()) {
+                *byte = (sample.to_int(8) + 128) as u8;
+            }
+        }
+        BitResolution::Signed16Bit => {
+            for (sample, bytes) in input.iter().zip(output.chunks_exact_mut(2)) {
+                LittleEndian::write_i16(bytes, sample.to_int(16) as i16);
+            }
+        }
+        BitResolution::Signed24Bit => {
+            for (sample, bytes) in input.iter().zip(output.chunks_exact_mut(3)) {
+                LittleEndian::write_i24(bytes, sample.to_int(24));
+            }
+        }
+        BitResolution::Signed32Bit => {
+            for (sample, bytes) in input.iter().zip(output.chunks_exact_mut(4)) {
+                LittleEndian::write_i32(bytes, sample.to_int(32));
+            }
+        }
+        BitResolution::Float32Bit => {
+            for (sample, bytes) in input.iter().zip(output.chunks_exact_mut(4)) {
+                LittleEndian::write_f32(bytes, sample.to_f64() as f32);
+            }
+        }
+        BitResolution::Float64Bit => {
+            for (sample, bytes) in input.iter().zip(output.chunks_exact_mut(8)) {
+                LittleEndian::write_f64(bytes, sample.to_f64());
+            }
+        }
+        BitResolution::Signed12Bit | BitResolution::Signed10Bit => {
+            pack(bit_resolution.bits() as u32, input, output);
+        }
+    }
+
+    Ok(len)
+}
+
+/// Decodes interleaved samples from `input` into `output`, returning the
+/// number of samples written. Trailing bytes that do not hold a whole sample
+/// are ignored.
+pub fn decode<S: CodecSample>(
+    bit_resolution: BitResolution,
+    input: &[u8],
+    output: &mut [S],
+) -> Result<usize, Error> {
+    let len = decoded_len(bit_resolution, input.len());
+    if output.len() < len {
+        return Err(Error::BufferTooShort {
+            expected: len,
+            actual: output.len(),
+        });
+    }
+    let output = &mut output[..len];
+
+    match bit_resolution {
+        BitResolution::Unsigned8Bit => {
+            for (sample, byte) in output.iter_mut().zip(input.iter()) {
+                *sample = S::from_int(*byte as i32 - 128, 8);
+            }
+        }
+        BitResolution::Signed16Bit => {
+            for (sample, bytes) in output.iter_mut().zip(input.chunks_exact(2)) {
+                *sample = S::from_int(LittleEndian::read_i16(bytes) as i32, 16);
+            }
+        }
+        BitResolution::Signed24Bit => {
+            for (sample, bytes) in output.iter_mut().zip(input.chunks_exact(3)) {
+                *sample = S::from_int(LittleEndian::read_i24(bytes), 24);
+            }
+        }
+        BitResolution::Signed32Bit => {
+            for (sample, bytes) in output.iter_mut().zip(input.chunks_exact(4)) {
+                *sample = S::from_int(LittleEndian::read_i32(bytes), 32);
+            }
+        }
+        BitResolution::Float32Bit => {
+            for (sample, bytes) in output.iter_mut().zip(input.chunks_exact(4)) {
+                *sample = S::from_f64(LittleEndian::read_f32(bytes) as f64);
+            }
+        }
+        BitResolution::Float64Bit => {
+            for (sample, bytes) in output.iter_mut().zip(input.chunks_exact(8)) {
+                *sample = S::from_f64(LittleEndian::read_f64(bytes));
+            }
+        }
+        BitResolution::Signed12Bit | BitResolution::Signed10Bit => {
+            unpack(bit_resolution.bits() as u32, input, output);
+        }
+    }
+
+    Ok(len)
+}
+
+/// Packs samples narrower than a byte boundary back to back, least
+/// significant bit first (two 12 bit samples in 3 bytes, four 10 bit samples
+/// in 5 bytes).
+fn pack<S: CodecSample>(bits: u32, input: &[S], output: &mut [u8]) {
+    let mask = (1u64 << bits) - 1;
+    let mut acc = 0u64;
+    let mut acc_bits = 0;
+    let mut bytes = output.iter_mut();
+
+    for sample in input {
+        acc |= (sample.to_int(bits) as u64 & mask) << acc_bits;
+        acc_bits += bits;
+        while acc_bits >= 8 {
+            if let Some(byte) = bytes.next() {
+                *byte = acc as u8;
+            }
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    }
+    if acc_bits > 0 {
+        if let Some(byte) = bytes.next() {
+            *byte = acc as u8;
+        }
+    }
+}
+
+fn unpack<S: CodecSample>(bits: u32, input: &[u8], output: &mut [S]) {
+    let mut acc = 0u64;
+    let mut acc_bits = 0;
+    let mut bytes = input.iter();
+
+    for sample in output {
+        while acc_bits < bits {
+            let byte = bytes.next().copied().unwrap_or(0);
+            acc |= (byte as u64) << acc_bits;
+            acc_bits += 8;
+        }
+        // Sign extend from `bits` to 32 bits
+        let value = ((acc as u32) << (32 - bits)) as i32 >> (32 - bits);
+        *sample = S::from_int(value, bits);
+        acc >>= bits;
+        acc_bits -= bits;
+    }
+}
+
+/// Iterator over the interleaved samples of an encoded payload, decoding
+/// them one at a time without allocating.
+pub struct Samples<'a, S> {
+    bit_resolution: BitResolution,
+    data: &'a [u8],
+    index: usize,
+    len: usize,
+    sample: PhantomData<S>,
+}
+
+/// Returns an iterator decoding every whole sample stored in `data`.
+pub fn samples<S: CodecSample>(bit_resolution: BitResolution, data: &[u8]) -> Samples<'_, S> {
+    Samples {
+        bit_resolution,
+        data,
+        index: 0,
+        len: decoded_len(bit_resolution, data.len()),
+        sample: PhantomData,
+    }
+}
+
+impl<S: CodecSample> Iterator for Samples<'_, S> {
+    type Item = S;
+
+    fn next(&mut self) -> Option<S> {
+        if self.index >= self.len {
+            return None;
+        }
+        let sample = decode_sample(self.bit_resolution, self.data, self.index);
+        self.index += 1;
+
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<S: CodecSample> ExactSizeIterator for Samples<'_, S> {}
+
+/// Decodes the sample at `index`, which must be below `decoded_len`.
+fn decode_sample<S: CodecSample>(bit_resolution: BitResolution, data: &[u8], index: usize) -> S {
+    match bit_resolution {
+        BitResolution::Unsigned8Bit => S::from_int(data[index] as i32 - 128, 8),
+        BitResolution::Signed16Bit => {
+            S::from_int(LittleEndian::read_i16(&data[index * 2..]) as i32, 16)
+        }
+        BitResolution::Signed24Bit => S::from_int(LittleEndian::read_i24(&data[index * 3..]), 24),
+        BitResolution::Signed32Bit => S::from_int(LittleEndian::read_i32(&data[index * 4..]), 32),
+        BitResolution::Float32Bit => S::from_f64(LittleEndian::read_f32(&data[index * 4..]) as f64),
+        BitResolution::Float64Bit => S::from_f64(LittleEndian::read_f64(&data[index * 8..])),
+        BitResolution::Signed12Bit | BitResolution::Signed10Bit => {
+            let bits = bit_resolution.bits() as u32;
+            let bit = index * bits as usize;
+            // A packed sample spans at most 3 bytes
+            let acc = data[bit / 8..]
+                .iter()
+                .take(3)
+                .enumerate()
+                .fold(0u32, |acc, (i, byte)| acc | (*byte as u32) << (8 * i));
+            let value = (((acc >> (bit % 8)) << (32 - bits)) as i32) >> (32 - bits);
+            S::from_int(value, bits)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTIONS: [BitResolution; 8] = [
+        BitResolution::Unsigned8Bit,
+        BitResolution::Signed16Bit,
+        BitResolution::Signed24Bit,
+        BitResolution::Signed32Bit,
+        BitResolution::Float32Bit,
+        BitResolution::Float64Bit,
+        BitResolution::Signed12Bit,
+        BitResolution::Signed10Bit,
+    ];
+
+    fn round_trip<S: CodecSample>(bit_resolution: BitResolution, input: &[S]) -> Vec<S> {
+        let mut encoded = vec![0; encoded_len(bit_resolution, input.len())];
+        assert_eq!(
+            encode(bit_resolution, input, &mut encoded),
+            Ok(encoded.len())
+        );
+        let mut decoded = vec![input[0]; input.len()];
+        assert_eq!(
+            decode(bit_resolution, &encoded, &mut decoded),
+            Ok(input.len())
+        );
+        let iterated = samples::<S>(bit_resolution, &encoded).collect::<Vec<_>>();
+        assert_eq!(iterated.len(), decoded.len());
+        for (a, b) in iterated.iter().zip(decoded.iter()) {
+            assert_eq!(a.to_f64(), b.to_f64());
+        }
+
+        decoded
+    }
+
+    #[test]
+    fn round_trips_every_resolution() {
+        for bit_resolution in RESOLUTIONS {
+            // Full scale is one step below 1.0 for integer resolutions
+            let step = match bit_resolution {
+                BitResolution::Float32Bit | BitResolution::Float64Bit => 0.0,
+                _ => 1.0 / (1i64 << (bit_resolution.bits() - 1)) as f64,
+            };
+            let input = [-1.0, -0.5, -step, 0.0, step, 0.25, 1.0 - step];
+            let output = round_trip(bit_resolution, &input);
+            assert_eq!(output, input, "{:?}", bit_resolution);
+        }
+    }
+
+    #[test]
+    fn round_trips_integer_samples() {
+        let input = [i32::MIN, -1 << 16, 0, 1 << 24, i32::MAX & !0xffff];
+        assert_eq!(round_trip(BitResolution::Signed16Bit, &input), input);
+        assert_eq!(round_trip(BitResolution::Signed24Bit, &input), input);
+        assert_eq!(round_trip(BitResolution::Signed32Bit, &input), input);
+        assert_eq!(
+            round_trip(BitResolution::Signed32Bit, &[i32::MAX]),
+            [i32::MAX]
+        );
+    }
+
+    #[test]
+    fn sign_extends_24_bit_samples() {
+        let data = [0x00, 0x00, 0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        let mut output = [0i32; 3];
+        assert_eq!(
+            decode(BitResolution::Signed24Bit, &data, &mut output),
+            Ok(3)
+        );
+        assert_eq!(output, [i32::MIN, -256, 0x7fff_ff00]);
+
+        let mut output = [0.0f64; 3];
+        decode(BitResolution::Signed24Bit, &data, &mut output).unwrap();
+        assert_eq!(output[0], -1.0);
+        assert_eq!(output[1], -1.0 / (1 << 23) as f64);
+    }
+
+    #[test]
+    fn offsets_unsigned_8_bit_samples() {
+        let mut encoded = [0; 4];
+        encode(
+            BitResolution::Unsigned8Bit,
+            &[-1.0f32, 0.0, 0.5, 1.0],
+            &mut encoded,
+        )
+        .unwrap();
+        assert_eq!(encoded, [0x00, 0x80, 0xc0, 0xff]);
+
+        let mut decoded = [0.0f32; 3];
+        decode(
+            BitResolution::Unsigned8Bit,
+            &[0x00, 0x80, 0xff],
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, [-1.0, 0.0, 127.0 / 128.0]);
+    }
+
+    #[test]
+    fn clips_at_full_scale() {
+        let input = [1.0f64, 2.0, -1.0, -2.0];
+
+        let mut encoded = [0; 8];
+        encode(BitResolution::Signed16Bit, &input, &mut encoded).unwrap();
+        assert_eq!(encoded, [0xff, 0x7f, 0xff, 0x7f, 0x00, 0x80, 0x00, 0x80]);
+
+        let mut encoded = [0; 12];
+        encode(BitResolution::Signed24Bit, &input, &mut encoded).unwrap();
+        assert_eq!(
+            encoded,
+            [0xff, 0xff, 0x7f, 0xff, 0xff, 0x7f, 0x00, 0x00, 0x80, 0x00, 0x00, 0x80]
+        );
+
+        let mut encoded = [0; 16];
+        encode(BitResolution::Signed32Bit, &input, &mut encoded).unwrap();
+        let mut decoded = [0i32; 4];
+        decode(BitResolution::Signed32Bit, &encoded, &mut decoded).unwrap();
+        assert_eq!(decoded, [i32::MAX, i32::MAX, i32::MIN, i32::MIN]);
+    }
+
+    #[test]
+    fn packs_12_bit_samples() {
+        // 0x123 and 0x456 back to back from the low bits of the first byte
+        let data = [0x23, 0x61, 0x45, 0x00, 0xf8, 0xff];
+        let expected = [0x123, 0x456, -0x800, -1].map(|value: i32| value << 20);
+
+        let mut decoded = [0i32; 4];
+        assert_eq!(
+            decode(BitResolution::Signed12Bit, &data, &mut decoded),
+            Ok(4)
+        );
+        assert_eq!(decoded, expected);
+        assert!(samples::<i32>(BitResolution::Signed12Bit, &data).eq(expected));
+
+        let mut encoded = [0; 6];
+        encode(BitResolution::Signed12Bit, &expected, &mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn packs_10_bit_samples() {
+        // Four samples in five bytes, the last one holding the top byte of
+        // 0x1 | 0x2 << 10 | -0x200 << 20 | -1 << 30
+        let data = [0x01, 0x08, 0x00, 0xe0, 0xff];
+        let expected = [0x1, 0x2, -0x200, -1].map(|value: i32| value << 22);
+
+        let mut decoded = [0i32; 4];
+        assert_eq!(
+            decode(BitResolution::Signed10Bit, &data, &mut decoded),
+            Ok(4)
+        );
+        assert_eq!(decoded, expected);
+        assert!(samples::<i32>(BitResolution::Signed10Bit, &data).eq(expected));
+
+        let mut encoded = [0; 5];
+        encode(BitResolution::Signed10Bit, &expected, &mut encoded).unwrap();
+        assert_eq!(encoded, data);
+    }
+
+    #[test]
+    fn ignores_trailing_partial_samples() {
+        // Three 10 bit samples need 30 bits, leaving two spare bits
+        assert_eq!(encoded_len(BitResolution::Signed10Bit, 3), 4);
+        assert_eq!(decoded_len(BitResolution::Signed10Bit, 4), 3);
+        assert_eq!(decoded_len(BitResolution::Signed12Bit, 4), 2);
+        assert_eq!(decoded_len(BitResolution::Signed24Bit, 4), 1);
+    }
+
+    #[test]
+    fn rejects_short_buffers() {
+        assert_eq!(
+            encode(BitResolution::Signed24Bit, &[0.0f32; 2], &mut [0; 5]),
+            Err(Error::BufferTooShort {
+                expected: 6,
+                actual: 5
+            })
+        );
+        assert_eq!(
+            decode(BitResolution::Signed16Bit, &[0; 6], &mut [0.0f32; 2]),
+            Err(Error::BufferTooShort {
+                expected: 3,
+                actual: 2
+            })
+        );
+    }
+}
@@ -7,6 +7,8 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use super::packet::Error;
 
 pub const MAX_NUM_SAMPLES: usize = 256;
+pub const MAX_NUM_CHANNELS: usize = 256;
+pub const STREAM_NAME_SIZE: usize = 16;
 const SAMPLE_RATE_MASK: u8 = 0b00011111;
 const SUB_PROTOCOL_MASK: u8 = 0b11100000;
 const RESERVED_MASK: u8 = 0b00001000;
@@ -14,31 +16,28 @@ const BIT_RESOLUTION_MASK: u8 = 0b00000111;
 const CODEC_MASK: u8 = 0b11110000;
 pub const HEADER_SIZE: usize = 28;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Header {
     sample_rate: SampleRate,
     sub_protocol: SubProtocol,
-    num_samples: u8,
-    num_channels: u8,
+    num_samples: u16,
+    num_channels: u16,
     bit_resolution: BitResolution,
     codec: Codec,
-    stream_name: [u8; 16],
+    stream_name: [u8; STREAM_NAME_SIZE],
     frame_number: u32,
 }
 
 impl Header {
     pub fn new(stream_name: &str) -> Self {
-        let mut stream_name_bytes = [0u8; 16];
-        stream_name_bytes[..stream_name.len()].copy_from_slice(stream_name.as_bytes());
-
         Self {
             sample_rate: SampleRate::Hz48000,
             sub_protocol: SubProtocol::Audio,
-            num_samples: MAX_NUM_SAMPLES as u8,
+            num_samples: MAX_NUM_SAMPLES as u16,
             num_channels: 2,
             bit_resolution: BitResolution::Signed16Bit,
             codec: Codec::PCM,
-            stream_name: stream_name_bytes,
+            stream_name: stream_name_bytes(stream_name),
             frame_number: 0,
         }
     }
@@ -47,34 +46,79 @@ impl Header {
         self.sample_rate
     }
 
+    pub fn set_sample_rate(&mut self, sample_rate: SampleRate) {
+        self.sample_rate = sample_rate;
+    }
+
     pub fn stream_name(&self) -> String {
-        String::from_utf8_lossy(&self.stream_name).replace("\0", "")
+        String::from_utf8_lossy(&self.stream_name).replace('\0', "")
+    }
+
+    /// Borrowed stream name, up to the first nul byte.
+    pub fn stream_name_str(&self) -> &str {
+        let len = self
+            .stream_name
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(STREAM_NAME_SIZE);
+        std::str::from_utf8(&self.stream_name[..len]).unwrap_or_default()
+    }
+
+    /// Sets the stream name, truncating it to the 16 bytes VBAN allows.
+    pub fn set_stream_name(&mut self, stream_name: &str) {
+        self.stream_name = stream_name_bytes(stream_name);
     }
 
     pub fn sub_protocol(&self) -> SubProtocol {
         self.sub_protocol
     }
 
-    pub fn num_samples(&self) -> u8 {
+    pub fn set_sub_protocol(&mut self, sub_protocol: SubProtocol) {
+        self.sub_protocol = sub_protocol;
+    }
+
+    /// Number of samples per channel carried by the packet (1 to 256).
+    pub fn num_samples(&self) -> u16 {
         self.num_samples
     }
 
-    pub fn set_num_samples(&mut self, num_samples: u8) {
-        self.num_samples = num_samples;
+    /// Sets the number of samples per channel, clamped to 1..=256.
+    pub fn set_num_samples(&mut self, num_samples: u16) {
+        self.num_samples = num_samples.clamp(1, MAX_NUM_SAMPLES as u16);
     }
 
-    pub fn num_channels(&self) -> u8 {
+    /// Number of channels carried by the packet (1 to 256).
+    pub fn num_channels(&self) -> u16 {
         self.num_channels
     }
 
+    /// Sets the number of channels, clamped to 1..=256.
+    pub fn set_num_channels(&mut self, num_channels: u16) {
+        self.num_channels = num_channels.clamp(1, MAX_NUM_CHANNELS as u16);
+    }
+
     pub fn bit_resolution(&self) -> BitResolution {
         self.bit_resolution
     }
 
+    pub fn set_bit_resolution(&mut self, bit_resolution: BitResolution) {
+        self.bit_resolution = bit_resolution;
+    }
+
     pub fn codec(&self) -> Codec {
         self.codec
     }
 
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// Size in bytes of the PCM payload described by this header.
+    pub fn payload_size(&self) -> usize {
+        let samples = self.num_samples as usize * self.num_channels as usize;
+        (samples * self.bit_resolution.bits()).div_ceil(8)
+    }
+
     pub fn frame_number(&self) -> u32 {
         self.frame_number
     }
@@ -82,34 +126,94 @@ impl Header {
     pub fn set_frame_number(&mut self, frame_number: u32) {
         self.frame_number = frame_number;
     }
+
+    /// Writes the header into the first `HEADER_SIZE` bytes of `buf`,
+    /// returning the number of bytes written.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.len() < HEADER_SIZE {
+            return Err(Error::BufferTooShort {
+                expected: HEADER_SIZE,
+                actual: buf.len(),
+            });
+        }
+        // Magic number
+        buf[0..4].copy_from_slice(b"VBAN");
+        buf[4] = self.sample_rate.to_u8().unwrap() | self.sub_protocol.to_u8().unwrap();
+        buf[5] = (self.num_samples - 1) as u8;
+        buf[6] = (self.num_channels - 1) as u8;
+        buf[7] = self.bit_resolution.to_u8().unwrap() | self.codec.to_u8().unwrap();
+        buf[8..24].copy_from_slice(&self.stream_name);
+        LittleEndian::write_u32(&mut buf[24..28], self.frame_number);
+
+        Ok(HEADER_SIZE)
+    }
+}
+
+impl std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "VBAN {:?} \"{}\" {:?} {}ch x {} samples {:?} {:?} #{}",
+            self.sub_protocol,
+            self.stream_name(),
+            self.sample_rate,
+            self.num_channels,
+            self.num_samples,
+            self.bit_resolution,
+            self.codec,
+            self.frame_number,
+        )
+    }
+}
+
+fn stream_name_bytes(stream_name: &str) -> [u8; STREAM_NAME_SIZE] {
+    let mut bytes = [0u8; STREAM_NAME_SIZE];
+    let mut len = stream_name.len().min(STREAM_NAME_SIZE);
+    while !stream_name.is_char_boundary(len) {
+        len -= 1;
+    }
+    bytes[..len].copy_from_slice(&stream_name.as_bytes()[..len]);
+    bytes
 }
 
 impl TryFrom<&[u8]> for Header {
     type Error = Error;
 
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < HEADER_SIZE {
+            return Err(Error::BufferTooShort {
+                expected: HEADER_SIZE,
+                actual: data.len(),
+            });
+        }
         if &data[0..4] != "VBAN".as_bytes() {
             return Err(Error::MissingMagicNumber);
         }
         let sr_sp = data[4];
-        let sample_rate = SampleRate::from_u8(sr_sp & SAMPLE_RATE_MASK).unwrap();
-        let sub_protocol = SubProtocol::from_u8(sr_sp & SUB_PROTOCOL_MASK).unwrap();
+        let sample_rate = SampleRate::from_u8(sr_sp & SAMPLE_RATE_MASK)
+            .ok_or(Error::UnknownSampleRate(sr_sp & SAMPLE_RATE_MASK))?;
+        let sub_protocol =
+            SubProtocol::from_u8(sr_sp & SUB_PROTOCOL_MASK).ok_or(Error::MalformedFormat)?;
         let samples_per_frame = data[5];
         let channels = data[6];
         let format_codec = data[7];
         if (format_codec & RESERVED_MASK) != 0 {
             return Err(Error::MalformedFormat);
         }
-        let bit_resolution = BitResolution::from_u8(format_codec & BIT_RESOLUTION_MASK).unwrap();
-        let codec = Codec::from_u8(format_codec & CODEC_MASK).unwrap();
-        let mut stream_name: [u8; 16] = [0; 16];
+        let bit_resolution = BitResolution::from_u8(format_codec & BIT_RESOLUTION_MASK)
+            .ok_or(Error::MalformedFormat)?;
+        let codec = Codec::from_u8(format_codec & CODEC_MASK).ok_or(Error::MalformedFormat)?;
+        let mut stream_name = [0; STREAM_NAME_SIZE];
         stream_name.copy_from_slice(&data[8..24]);
+        if !stream_name.is_ascii() {
+            return Err(Error::NonAsciiStreamName);
+        }
         let frame_number = LittleEndian::read_u32(&data[24..28]);
         Ok(Self {
             sample_rate,
             sub_protocol,
-            num_samples: samples_per_frame,
-            num_channels: channels + 1,
+            num_samples: samples_per_frame as u16 + 1,
+            num_channels: channels as u16 + 1,
             bit_resolution,
             codec,
             stream_name,
@@ -121,25 +225,14 @@ impl TryFrom<&[u8]> for Header {
 impl From<Header> for [u8; HEADER_SIZE] {
     fn from(header: Header) -> [u8; HEADER_SIZE] {
         let mut result = [0; HEADER_SIZE];
-        // Magic number
-        'V'.encode_utf8(&mut result[0..]);
-        'B'.encode_utf8(&mut result[1..]);
-        'A'.encode_utf8(&mut result[2..]);
-        'N'.encode_utf8(&mut result[3..]);
-        result[4] = header.sample_rate.to_u8().unwrap();
-        result[5] = header.num_samples;
-        result[6] = header.num_channels - 1;
-        result[7] = header.bit_resolution.to_u8().unwrap() + header.codec.to_u8().unwrap();
-        for i in 0..16 {
-            result[8 + i] = header.stream_name[i];
-        }
-        LittleEndian::write_u32(&mut result[24..28], header.frame_number);
+        // The array always fits the header
+        let _ = header.encode_into(&mut result);
 
         result
     }
 }
 
-#[derive(Clone, Copy, FromPrimitive, ToPrimitive, Debug)]
+#[derive(Clone, Copy, FromPrimitive, ToPrimitive, Debug, PartialEq, Eq, Hash)]
 pub enum SampleRate {
     Hz6000 = 0,
     Hz12000,
@@ -164,7 +257,49 @@ pub enum SampleRate {
     Hz705600,
 }
 
-#[derive(Clone, Copy, FromPrimitive, Debug)]
+impl SampleRate {
+    /// The VBAN sample rate matching `hz`, if there is one.
+    pub fn from_hz(hz: u32) -> Option<SampleRate> {
+        (0..=SAMPLE_RATE_MASK)
+            .filter_map(SampleRate::from_u8)
+            .find(|sample_rate| sample_rate.hz() == hz)
+    }
+
+    /// The sample rate in Hz.
+    pub fn hz(&self) -> u32 {
+        match self {
+            SampleRate::Hz6000 => 6000,
+            SampleRate::Hz12000 => 12000,
+            SampleRate::Hz24000 => 24000,
+            SampleRate::Hz48000 => 48000,
+            SampleRate::Hz96000 => 96000,
+            SampleRate::Hz192000 => 192000,
+            SampleRate::Hz384000 => 384000,
+            SampleRate::Hz8000 => 8000,
+            SampleRate::Hz16000 => 16000,
+            SampleRate::Hz32000 => 32000,
+            SampleRate::Hz64000 => 64000,
+            SampleRate::Hz128000 => 128000,
+            SampleRate::Hz256000 => 256000,
+            SampleRate::Hz512000 => 512000,
+            SampleRate::Hz11025 => 11025,
+            SampleRate::Hz22050 => 22050,
+            SampleRate::Hz44100 => 44100,
+            SampleRate::Hz88200 => 88200,
+            SampleRate::Hz176400 => 176400,
+            SampleRate::Hz352800 => 352800,
+            SampleRate::Hz705600 => 705600,
+        }
+    }
+}
+
+impl From<SampleRate> for u32 {
+    fn from(sample_rate: SampleRate) -> u32 {
+        sample_rate.hz()
+    }
+}
+
+#[derive(Clone, Copy, ToPrimitive, FromPrimitive, Debug, PartialEq, Eq, Hash)]
 pub enum SubProtocol {
     Audio = 0x00,
     Serial = 0x20,
@@ -176,7 +311,8 @@ pub enum SubProtocol {
     User = 0xe0,
 }
 
-#[derive(Clone, Copy, ToPrimitive, FromPrimitive, Debug)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, ToPrimitive, FromPrimitive, Debug, PartialEq, Eq, Hash)]
 pub enum BitResolution {
     Unsigned8Bit = 0,
     Signed16Bit,
@@ -188,7 +324,24 @@ pub enum BitResolution {
     Signed10Bit,
 }
 
-#[derive(Clone, Copy, ToPrimitive, FromPrimitive, Debug)]
+impl BitResolution {
+    /// Number of bits used by one sample on the wire.
+    pub fn bits(&self) -> usize {
+        match self {
+            BitResolution::Unsigned8Bit => 8,
+            BitResolution::Signed16Bit => 16,
+            BitResolution::Signed24Bit => 24,
+            BitResolution::Signed32Bit => 32,
+            BitResolution::Float32Bit => 32,
+            BitResolution::Float64Bit => 64,
+            BitResolution::Signed12Bit => 12,
+            BitResolution::Signed10Bit => 10,
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, ToPrimitive, FromPrimitive, Debug, PartialEq, Eq, Hash)]
 pub enum Codec {
     PCM = 0x00,
     VBCA = 0x10,
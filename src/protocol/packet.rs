@@ -1,43 +1,54 @@
 pub const MAX_PACKET_SIZE: usize = 1464;
 use std::convert::TryFrom;
 
+use super::codec::{self, CodecSample, Samples};
 use super::header::HEADER_SIZE;
-pub use super::header::{Codec, Header, SubProtocol};
+pub use super::header::{BitResolution, Codec, Header, SampleRate, SubProtocol};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Error {
     MissingMagicNumber,
     MalformedFormat,
+    BufferTooShort { expected: usize, actual: usize },
+    UnknownSampleRate(u8),
+    PayloadLengthMismatch { expected: usize, actual: usize },
+    NonAsciiStreamName,
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Error::MissingMagicNumber => "Missing magic number",
-                Error::MalformedFormat => "Malformed format",
-            }
-        )
-    }
-}
-
-impl std::error::Error for Error {
-    fn description(&self) -> &str {
         match self {
-            Error::MissingMagicNumber => "Missing magic number",
-            Error::MalformedFormat => "Malformed format",
+            Error::MissingMagicNumber => write!(f, "Missing magic number"),
+            Error::MalformedFormat => write!(f, "Malformed format"),
+            Error::BufferTooShort { expected, actual } => write!(
+                f,
+                "Buffer too short: expected at least {} bytes, got {}",
+                expected, actual
+            ),
+            Error::UnknownSampleRate(index) => write!(f, "Unknown sample rate index {}", index),
+            Error::PayloadLengthMismatch { expected, actual } => write!(
+                f,
+                "Payload length mismatch: expected {} bytes, got {}",
+                expected, actual
+            ),
+            Error::NonAsciiStreamName => write!(f, "Non-ASCII stream name"),
         }
     }
 }
 
+impl std::error::Error for Error {}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Packet {
     header: Header,
-    pub data: Vec<u8>,
+    data: Vec<u8>,
 }
 
 impl Packet {
+    pub fn new(header: Header, data: Vec<u8>) -> Self {
+        Self { header, data }
+    }
+
     pub fn header(&self) -> &Header {
         &self.header
     }
@@ -45,26 +56,273 @@ impl Packet {
     pub fn header_mut(&mut self) -> &mut Header {
         &mut self.header
     }
+
+    pub fn set_header(&mut self, header: Header) {
+        self.header = header;
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    pub fn set_data(&mut self, data: Vec<u8>) {
+        self.data = data;
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn as_packet_ref(&self) -> PacketRef<'_> {
+        PacketRef::new(self.header, &self.data)
+    }
+
+    /// Size in bytes of the encoded packet.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_SIZE + self.data.len()
+    }
+
+    /// Writes the header and payload into `buf`, returning the number of
+    /// bytes written.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.encoded_len();
+        if buf.len() < len {
+            return Err(Error::BufferTooShort {
+                expected: len,
+                actual: buf.len(),
+            });
+        }
+        self.header.encode_into(buf)?;
+        buf[HEADER_SIZE..len].copy_from_slice(&self.data);
+
+        Ok(len)
+    }
+}
+
+impl std::fmt::Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({} bytes)", self.header, self.data.len())
+    }
 }
 
 impl TryFrom<&[u8]> for Packet {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let pkt = Packet {
-            header: Header::try_from(value)?,
-            data: Vec::from(&value[HEADER_SIZE..]),
-        };
-        Ok(pkt)
+        Ok(PacketRef::try_from(value)?.to_packet())
+    }
+}
+
+/// A packet borrowing its payload from the receive buffer, so parsing it
+/// never allocates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PacketRef<'a> {
+    header: Header,
+    data: &'a [u8],
+}
+
+impl<'a> PacketRef<'a> {
+    pub fn new(header: Header, data: &'a [u8]) -> Self {
+        Self { header, data }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Decodes the payload samples in the bit resolution announced by the
+    /// header.
+    pub fn samples<S: CodecSample>(&self) -> Samples<'a, S> {
+        codec::samples(self.header.bit_resolution(), self.data)
+    }
+
+    pub fn to_packet(&self) -> Packet {
+        Packet::new(self.header, Vec::from(self.data))
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PacketRef<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let header = Header::try_from(value)?;
+        let data = &value[HEADER_SIZE..];
+        check_payload_len(&header, data)?;
+
+        Ok(PacketRef { header, data })
+    }
+}
+
+impl std::fmt::Display for PacketRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({} bytes)", self.header, self.data.len())
     }
 }
 
+/// Checks that a PCM audio payload holds exactly `num_samples * num_channels`
+/// samples of the announced bit resolution. Other sub protocols and codecs
+/// are not sized by the header, so they are accepted as is.
+fn check_payload_len(header: &Header, data: &[u8]) -> Result<(), Error> {
+    if !matches!(header.sub_protocol(), SubProtocol::Audio) || !matches!(header.codec(), Codec::PCM)
+    {
+        return Ok(());
+    }
+
+    let expected = header.payload_size();
+    if data.len() != expected {
+        return Err(Error::PayloadLengthMismatch {
+            expected,
+            actual: data.len(),
+        });
+    }
+
+    Ok(())
+}
+
 impl From<Packet> for Vec<u8> {
     fn from(pkt: Packet) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(HEADER_SIZE + pkt.data.len());
-        let header: [u8; HEADER_SIZE] = pkt.header.into();
-        buf.extend_from_slice(&header);
-        buf.extend_from_slice(&pkt.data);
+        let mut buf = vec![0; pkt.encoded_len()];
+        // The buffer is sized for the packet
+        let _ = pkt.encode_into(&mut buf);
         buf
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESOLUTIONS: [BitResolution; 8] = [
+        BitResolution::Unsigned8Bit,
+        BitResolution::Signed16Bit,
+        BitResolution::Signed24Bit,
+        BitResolution::Signed32Bit,
+        BitResolution::Float32Bit,
+        BitResolution::Float64Bit,
+        BitResolution::Signed12Bit,
+        BitResolution::Signed10Bit,
+    ];
+
+    fn encoded(bit_resolution: BitResolution) -> Vec<u8> {
+        let mut header = Header::new("Stream1");
+        header.set_num_samples(32);
+        header.set_bit_resolution(bit_resolution);
+        let data = vec![0; header.payload_size()];
+        Packet::new(header, data).into()
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Packet, Error> {
+        // Both parsers must agree
+        let header = Header::try_from(bytes);
+        let packet = PacketRef::try_from(bytes);
+        if let Err(e) = header {
+            assert_eq!(packet, Err(e));
+        }
+        packet.map(|packet| packet.to_packet())
+    }
+
+    #[test]
+    fn parses_what_it_encodes() {
+        for bit_resolution in RESOLUTIONS {
+            let bytes = encoded(bit_resolution);
+            let packet = parse(&bytes).unwrap();
+            assert_eq!(packet.header().bit_resolution(), bit_resolution);
+            assert_eq!(Vec::from(packet), bytes);
+        }
+    }
+
+    #[test]
+    fn rejects_short_buffers() {
+        let bytes = encoded(BitResolution::Signed16Bit);
+        for len in 0..HEADER_SIZE {
+            assert_eq!(
+                parse(&bytes[..len]),
+                Err(Error::BufferTooShort {
+                    expected: HEADER_SIZE,
+                    actual: len
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_missing_magic_number() {
+        let mut bytes = encoded(BitResolution::Signed16Bit);
+        bytes[3] = b'X';
+        assert_eq!(parse(&bytes), Err(Error::MissingMagicNumber));
+    }
+
+    #[test]
+    fn rejects_unknown_sample_rates() {
+        let mut bytes = encoded(BitResolution::Signed16Bit);
+        for index in 21..=31 {
+            bytes[4] = index;
+            assert_eq!(parse(&bytes), Err(Error::UnknownSampleRate(index)));
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_format_bit() {
+        let mut bytes = encoded(BitResolution::Signed16Bit);
+        bytes[7] |= 0b00001000;
+        assert_eq!(parse(&bytes), Err(Error::MalformedFormat));
+    }
+
+    #[test]
+    fn rejects_non_ascii_stream_names() {
+        let mut bytes = encoded(BitResolution::Signed16Bit);
+        bytes[8] = 0xc3;
+        assert_eq!(parse(&bytes), Err(Error::NonAsciiStreamName));
+    }
+
+    #[test]
+    fn rejects_payload_length_mismatches() {
+        for bit_resolution in RESOLUTIONS {
+            let bytes = encoded(bit_resolution);
+            let expected = bytes.len() - HEADER_SIZE;
+
+            let mut long = bytes.clone();
+            long.push(0);
+            assert_eq!(
+                parse(&long),
+                Err(Error::PayloadLengthMismatch {
+                    expected,
+                    actual: expected + 1
+                }),
+                "{:?}",
+                bit_resolution
+            );
+            assert_eq!(
+                parse(&bytes[..bytes.len() - 1]),
+                Err(Error::PayloadLengthMismatch {
+                    expected,
+                    actual: expected - 1
+                }),
+                "{:?}",
+                bit_resolution
+            );
+        }
+    }
+
+    #[test]
+    fn never_panics_on_any_header_byte() {
+        let bytes = encoded(BitResolution::Signed24Bit);
+        for index in 0..HEADER_SIZE {
+            for value in 0..=u8::MAX {
+                let mut bytes = bytes.clone();
+                bytes[index] = value;
+                if let Ok(packet) = parse(&bytes) {
+                    packet.as_packet_ref().samples::<f32>().count();
+                }
+            }
+        }
+    }
+}
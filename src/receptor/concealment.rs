@@ -0,0 +1,202 @@
+/// Length of the fades in and out of concealed audio, in seconds.
+const FADE_SECS: f64 = 0.005;
+/// Length of the recent audio kept to be repeated over a gap, in seconds.
+const HISTORY_SECS: f64 = 0.02;
+/// How long the repeated audio plays before fading out, in seconds.
+const MAX_REPEAT_SECS: f64 = 0.06;
+
+/// How the receptor fills audio lost on the network or to an underrun.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Concealment {
+    /// Output silence, switching abruptly in and out of it.
+    Silence,
+    /// Fade the last received audio out while repeating it, and fade back
+    /// in when data resumes.
+    #[default]
+    Fade,
+    /// Loop the last few milliseconds of audio for short gaps, crossfading
+    /// at the loop boundary, and fade out on longer ones.
+    Repeat,
+}
+
+/// Replaces missing output frames according to a `Concealment` strategy and
+/// crossfades back into the received audio when it resumes.
+pub struct Concealer {
+    strategy: Concealment,
+    channels: usize,
+    fade_frames: usize,
+    repeat_frames: usize,
+    seam_frames: usize,
+    history: Vec<f64>,
+    history_pos: usize,
+    concealed: Vec<f64>,
+    missing: usize,
+    recovery: usize,
+}
+
+impl Concealer {
+    pub fn new(strategy: Concealment, channels: usize, sample_rate: u32) -> Self {
+        let frames = |secs: f64| ((secs * sample_rate as f64) as usize).max(1);
+        let history_frames = frames(HISTORY_SECS);
+
+        Self {
+            strategy,
+            channels,
+            fade_frames: frames(FADE_SECS),
+            repeat_frames: frames(MAX_REPEAT_SECS),
+            // Leaves most of the history to the loop itself
+            seam_frames: frames(FADE_SECS).min(history_frames / 2),
+            history: vec![0.0; history_frames * channels],
+            history_pos: 0,
+            concealed: vec![0.0; channels],
+            missing: 0,
+            recovery: 0,
+        }
+    }
+
+    /// Processes one output frame in place. `received` is false when the
+    /// jitter buffer ran dry; frames holding `MISSING_SAMPLE` are treated
+    /// the same way.
+    pub fn process(&mut self, frame: &mut [f64], received: bool) {
+        if !received || frame.iter().any(|sample| sample.is_nan()) {
+            self.conceal(frame);
+            self.missing = self.missing.saturating_add(1);
+            self.recovery = self.fade_frames;
+            return;
+        }
+
+        if self.missing > 0 {
+            if self.recovery == 0 || self.strategy == Concealment::Silence {
+                self.missing = 0;
+                self.recovery = 0;
+            } else {
+                // Crossfade from the concealed audio into the received audio
+                let weight =
+                    (self.fade_frames - self.recovery + 1) as f64 / (self.fade_frames + 1) as f64;
+                let mut concealed = std::mem::take(&mut self.concealed);
+                self.conceal(&mut concealed);
+                for (sample, concealed) in frame.iter_mut().zip(&concealed) {
+                    *sample = concealed + (*sample - concealed) * weight;
+                }
+                self.concealed = concealed;
+                self.missing = self.missing.saturating_add(1);
+                self.recovery -= 1;
+                return;
+            }
+        }
+
+        let start = self.history_pos * self.channels;
+        self.history[start..start + self.channels].copy_from_slice(frame);
+        self.history_pos = (self.history_pos + 1) % self.history_frames();
+    }
+
+    fn conceal(&self, output: &mut [f64]) {
+        let gain = match self.strategy {
+            Concealment::Silence => 0.0,
+            Concealment::Fade => self.fade_gain(self.missing),
+            Concealment::Repeat => self.fade_gain(self.missing.saturating_sub(self.repeat_frames)),
+        };
+        if gain == 0.0 {
+            output.fill(0.0);
+            return;
+        }
+
+        // The history is looped over its frames following the seam, the last
+        // received frame being followed by the one a loop period earlier
+        let seam = self.seam_frames;
+        let period = self.history_frames() - seam;
+        let position = self.missing % period;
+        let looped = self.history_frame(seam + position);
+        // Crossfade the end of each loop into the frames preceding its start
+        let (next, next_weight) = match (position + seam).checked_sub(period) {
+            Some(index) => (
+                self.history_frame(index),
+                (index + 1) as f64 / (seam + 1) as f64,
+            ),
+            None => (looped, 0.0),
+        };
+        // Crossfade from the last received frame, decaying, into the loop
+        let tail = self.history_frame(self.history_frames() - 1);
+        let tail_weight = if self.missing < seam {
+            1.0 - (self.missing + 1) as f64 / (seam + 1) as f64
+        } else {
+            0.0
+        };
+
+        for (channel, output) in output.iter_mut().enumerate() {
+            let repeated = looped[channel] + (next[channel] - looped[channel]) * next_weight;
+            *output = (repeated + (tail[channel] - repeated) * tail_weight) * gain;
+        }
+    }
+
+    /// Frame `index` of the history, counting from its oldest frame.
+    fn history_frame(&self, index: usize) -> &[f64] {
+        let start = (self.history_pos + index) % self.history_frames() * self.channels;
+        &self.history[start..start + self.channels]
+    }
+
+    /// Gain `frames` frames into a fade out.
+    fn fade_gain(&self, frames: usize) -> f64 {
+        (1.0 - frames as f64 / self.fade_frames as f64).max(0.0)
+    }
+
+    fn history_frames(&self) -> usize {
+        self.history.len() / self.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Runs a 440 Hz sine through the concealer with a gap of `missing`
+    /// frames, returning the largest step between consecutive outputs.
+    fn largest_step(strategy: Concealment, missing: usize) -> f64 {
+        let mut concealer = Concealer::new(strategy, 1, SAMPLE_RATE);
+        let mut previous = 0.0;
+        let mut largest = 0.0f64;
+        for i in 0..4800 + missing {
+            let phase = i as f64 * 440.0 * std::f64::consts::TAU / SAMPLE_RATE as f64;
+            let mut frame = [phase.sin()];
+            let received = !(2400..2400 + missing).contains(&i);
+            concealer.process(&mut frame, received);
+            largest = largest.max((frame[0] - previous).abs());
+            previous = frame[0];
+        }
+
+        largest
+    }
+
+    #[test]
+    fn conceals_gaps_without_clicks() {
+        // A 440 Hz sine moves at most 0.058 per frame at 48 kHz
+        for strategy in [Concealment::Fade, Concealment::Repeat] {
+            for missing in [1, 100, 500, 960, 5000] {
+                let step = largest_step(strategy, missing);
+                assert!(step < 0.1, "{:?} {}: {}", strategy, missing, step);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_switches_abruptly() {
+        assert!(largest_step(Concealment::Silence, 100) > 0.1);
+    }
+
+    #[test]
+    fn fades_out_to_silence() {
+        for strategy in [Concealment::Fade, Concealment::Repeat] {
+            let mut concealer = Concealer::new(strategy, 2, SAMPLE_RATE);
+            for _ in 0..1000 {
+                concealer.process(&mut [0.5, -0.5], true);
+            }
+            let mut frame = [0.0; 2];
+            for _ in 0..SAMPLE_RATE / 10 {
+                concealer.process(&mut frame, false);
+            }
+            assert_eq!(frame, [0.0, 0.0], "{:?}", strategy);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::utils::resample::{self, ResampleQuality};
+
+use super::mixer::{ChannelMap, ChannelMixer};
+use super::stream::VbanStreamProducer;
+
+/// Converts packets from the incoming sample rate and channel layout to the
+/// output device ones before they are queued in the ring buffer.
+pub struct Converter {
+    quality: ResampleQuality,
+    channel_map: ChannelMap,
+    output_rate: u32,
+    output_channels: usize,
+    resampler: resample::Resampler,
+    mixer: ChannelMixer,
+    producer: VbanStreamProducer,
+    output: Vec<f64>,
+}
+
+impl Converter {
+    pub fn new(
+        quality: ResampleQuality,
+        channel_map: ChannelMap,
+        output_rate: u32,
+        output_channels: usize,
+        producer: VbanStreamProducer,
+    ) -> Self {
+        Self {
+            quality,
+            resampler: resample::Resampler::new(quality, output_rate, output_rate, output_channels),
+            mixer: ChannelMixer::new(&channel_map, output_channels, output_channels),
+            channel_map,
+            output_rate,
+            output_channels,
+            producer,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn into_producer(self) -> VbanStreamProducer {
+        self.producer
+    }
+
+    /// Sets the format of the samples pushed next, starting over when it
+    /// changes.
+    pub fn set_input_format(&mut self, sample_rate: u32, channels: usize) {
+        if sample_rate == self.resampler.input_rate() && channels == self.resampler.channels() {
+            return;
+        }
+        self.resampler =
+            resample::Resampler::new(self.quality, sample_rate, self.output_rate, channels);
+        self.mixer = ChannelMixer::new(&self.channel_map, channels, self.output_channels);
+    }
+
+    /// Converts a whole packet of interleaved samples and queues it, dropping
+    /// it rather than a partial frame when the ring buffer is full.
+    pub fn push<I: Iterator<Item = f64>>(&mut self, samples: &mut I) {
+        let mixer = &self.mixer;
+        let output = &mut self.output;
+        output.clear();
+        self.resampler
+            .process(samples, |frame| mixer.mix(frame, output));
+
+        if self.producer.free_len() >= output.len() {
+            self.producer.push_slice(output);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use super::stream::VbanStreamConsumer;
+
+/// Largest playback speed correction, in parts per one (2000 ppm).
+const MAX_CORRECTION: f64 = 0.002;
+/// Proportional gain applied to the relative fill error.
+const PROPORTIONAL_GAIN: f64 = 0.001;
+/// Integral gain, per second of relative fill error.
+const INTEGRAL_GAIN: f64 = 0.000_05;
+/// Time constant of the fill level smoothing, in seconds.
+const FILL_SMOOTHING_SECS: f64 = 1.0;
+
+/// Jitter buffer figures shared with the receptor owner.
+#[derive(Default)]
+pub struct JitterStats {
+    latency: AtomicU64,
+    drift_ppm: AtomicU64,
+}
+
+impl JitterStats {
+    /// Audio currently queued between the network and the output device.
+    pub fn latency(&self) -> Duration {
+        Duration::from_secs_f64(f64::from_bits(self.latency.load(Ordering::Relaxed)))
+    }
+
+    /// Estimated clock drift between the sender and the output device, in
+    /// parts per million. Positive when the sender runs faster.
+    pub fn drift_ppm(&self) -> f64 {
+        f64::from_bits(self.drift_ppm.load(Ordering::Relaxed))
+    }
+}
+
+/// Reads frames from the receive ring buffer at a slightly adjusted rate so
+/// its fill level stays near the target latency, compensating the clock
+/// drift between the sender and the output device.
+pub struct JitterBuffer {
+    consumer: VbanStreamConsumer,
+    channels: usize,
+    sample_rate: f64,
+    target: f64,
+    fill: f64,
+    integral: f64,
+    ratio: f64,
+    position: f64,
+    previous: Vec<f64>,
+    current: Vec<f64>,
+    buffering: bool,
+    stats: Arc<JitterStats>,
+}
+
+impl JitterBuffer {
+    pub fn new(
+        consumer: VbanStreamConsumer,
+        channels: usize,
+        sample_rate: u32,
+        target_frames: usize,
+        stats: Arc<JitterStats>,
+    ) -> Self {
+        Self {
+            consumer,
+            channels,
+            sample_rate: sample_rate as f64,
+            target: target_frames.max(1) as f64,
+            fill: target_frames as f64,
+            integral: 0.0,
+            ratio: 1.0,
+            position: 0.0,
+            previous: vec![0.0; channels],
+            current: vec![0.0; channels],
+            buffering: false,
+            stats,
+        }
+    }
+
+    /// Measures the fill level and adjusts the playback ratio. Called once
+    /// per output callback of `frames` frames.
+    pub fn update(&mut self, frames: usize) {
+        let dt = frames as f64 / self.sample_rate;
+        let fill = (self.consumer.len() / self.channels) as f64;
+        let alpha = (dt / FILL_SMOOTHING_SECS).min(1.0);
+        self.fill += (fill - self.fill) * alpha;
+
+        if self.buffering {
+            // Wait for the target latency to build up again after an underrun
+            self.buffering = fill < self.target;
+        } else {
+            let error = (self.fill - self.target) / self.target;
+            self.integral =
+                (self.integral + INTEGRAL_GAIN * error * dt).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+            self.ratio = 1.0
+                + (PROPORTIONAL_GAIN * error + self.integral)
+                    .clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        }
+
+        self.stats
+            .latency
+            .store((self.fill / self.sample_rate).to_bits(), Ordering::Relaxed);
+        self.stats
+            .drift_ppm
+            .store((self.integral * 1_000_000.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Writes the next output frame, interpolating between received frames.
+    /// Returns false and writes silence when the buffer ran dry.
+    pub fn next_frame(&mut self, output: &mut [f64]) -> bool {
+        if self.buffering {
+            output.fill(0.0);
+            return false;
+        }
+
+        self.position += self.ratio;
+        while self.position >= 1.0 {
+            if self.consumer.len() < self.channels {
+                self.buffering = true;
+                output.fill(0.0);
+                return false;
+            }
+            std::mem::swap(&mut self.previous, &mut self.current);
+            self.consumer.pop_slice(&mut self.current);
+            self.position -= 1.0;
+        }
+
+        let position = self.position;
+        for ((output, previous), current) in
+            output.iter_mut().zip(&self.previous).zip(&self.current)
+        {
+            *output = previous + (current - previous) * position;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ringbuf::HeapRb;
+
+    const SAMPLE_RATE: u32 = 1_000;
+    const CALLBACK_FRAMES: usize = 10;
+    const TARGET_FRAMES: usize = 20;
+
+    /// Feeds the buffer `drift_ppm` faster than the output drains it for
+    /// `secs` seconds, returning the stats and whether the buffer ran dry
+    /// during the second half.
+    fn run(drift_ppm: f64, secs: usize) -> (Arc<JitterStats>, bool) {
+        let (mut producer, consumer) = HeapRb::<f64>::new(TARGET_FRAMES * 4).split();
+        for _ in 0..TARGET_FRAMES {
+            producer.push(0.0).unwrap();
+        }
+        let stats = Arc::new(JitterStats::default());
+        let mut jitter = JitterBuffer::new(consumer, 1, SAMPLE_RATE, TARGET_FRAMES, stats.clone());
+
+        let callbacks = secs * SAMPLE_RATE as usize / CALLBACK_FRAMES;
+        let frames_per_callback = CALLBACK_FRAMES as f64 * (1.0 + drift_ppm / 1_000_000.0);
+        let mut pending = 0.0;
+        let mut ran_dry = false;
+        let mut output = [0.0];
+        for callback in 0..callbacks {
+            pending += frames_per_callback;
+            while pending >= 1.0 {
+                producer.push(0.5).unwrap();
+                pending -= 1.0;
+            }
+
+            jitter.update(CALLBACK_FRAMES);
+            for _ in 0..CALLBACK_FRAMES {
+                if !jitter.next_frame(&mut output) && callback >= callbacks / 2 {
+                    ran_dry = true;
+                }
+            }
+        }
+
+        (stats, ran_dry)
+    }
+
+    #[test]
+    fn settles_at_the_target_latency_and_estimates_drift() {
+        let target = TARGET_FRAMES as f64 / SAMPLE_RATE as f64;
+        for drift_ppm in [-500.0, -100.0, 0.0, 100.0, 500.0] {
+            let (stats, ran_dry) = run(drift_ppm, 300);
+            assert!(!ran_dry, "{} ppm", drift_ppm);
+
+            let latency = stats.latency().as_secs_f64();
+            assert!(
+                (latency - target).abs() < target * 0.05,
+                "{} ppm: latency {:?}",
+                drift_ppm,
+                stats.latency()
+            );
+            assert!(
+                (stats.drift_ppm() - drift_ppm).abs() < 10.0,
+                "{} ppm: estimated {} ppm",
+                drift_ppm,
+                stats.drift_ppm()
+            );
+        }
+    }
+}
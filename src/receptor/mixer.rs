@@ -0,0 +1,213 @@
+/// How incoming stream channels are routed to the output device channels.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChannelMap {
+    /// Plays a mono stream on every device channel, averages every stream
+    /// channel into a mono device and otherwise plays stream channel `n` on
+    /// device channel `n`, averaging extra stream channels into the device
+    /// channels they fold onto in order.
+    #[default]
+    Auto,
+    /// The stream channel played on each device channel, in device channel
+    /// order. `None`, missing entries and channels the stream does not have
+    /// leave the device channel silent.
+    Routes(Vec<Option<usize>>),
+}
+
+/// Mixes frames of stream channels into frames of device channels according
+/// to a `ChannelMap`.
+pub struct ChannelMixer {
+    inputs: usize,
+    identity: bool,
+    // Gain of each stream channel in each device channel, row per device channel
+    gains: Vec<f64>,
+}
+
+impl ChannelMixer {
+    pub fn new(channel_map: &ChannelMap, inputs: usize, outputs: usize) -> Self {
+        let mut gains = vec![0.0; inputs * outputs];
+
+        match channel_map {
+            ChannelMap::Auto if inputs == outputs || inputs == 1 => {
+                for output in 0..outputs {
+                    gains[output * inputs + output % inputs] = 1.0;
+                }
+            }
+            ChannelMap::Auto => {
+                // Average every stream channel folded onto a device channel
+                for input in 0..inputs {
+                    let output = input % outputs;
+                    let folded = (inputs - output).div_ceil(outputs);
+                    gains[output * inputs + input] = 1.0 / folded as f64;
+                }
+            }
+            ChannelMap::Routes(routes) => {
+                for (output, route) in routes.iter().take(outputs).enumerate() {
+                    if let Some(input) = route.filter(|input| *input < inputs) {
+                        gains[output * inputs + input] = 1.0;
+                    }
+                }
+            }
+        }
+
+        let identity = inputs == outputs
+            && gains
+                .chunks_exact(inputs)
+                .enumerate()
+                .all(|(output, gains)| {
+                    gains
+                        .iter()
+                        .enumerate()
+                        .all(|(input, gain)| *gain == if input == output { 1.0 } else { 0.0 })
+                });
+
+        Self {
+            inputs,
+            identity,
+            gains,
+        }
+    }
+
+    /// Appends the device frame mixed from a stream frame to `output`.
+    pub fn mix(&self, frame: &[f64], output: &mut Vec<f64>) {
+        if self.identity {
+            output.extend_from_slice(frame);
+            return;
+        }
+
+        output.extend(self.gains.chunks_exact(self.inputs).map(|gains| {
+            gains
+                .iter()
+                .zip(frame)
+                .filter(|(gain, _)| **gain != 0.0)
+                .map(|(gain, sample)| gain * sample)
+                .sum::<f64>()
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gain of each stream channel in each device channel, measured by
+    /// mixing one stream channel at a time.
+    fn gains(channel_map: &ChannelMap, inputs: usize, outputs: usize) -> Vec<Vec<f64>> {
+        let mixer = ChannelMixer::new(channel_map, inputs, outputs);
+        let columns = (0..inputs)
+            .map(|input| {
+                let mut frame = vec![0.0; inputs];
+                frame[input] = 1.0;
+                let mut output = Vec::new();
+                mixer.mix(&frame, &mut output);
+                assert_eq!(output.len(), outputs);
+                output
+            })
+            .collect::<Vec<_>>();
+
+        (0..outputs)
+            .map(|output| columns.iter().map(|column| column[output]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn auto_map_gains() {
+        let third = 1.0 / 3.0;
+        let cases = [
+            // Mono on every device channel
+            (1, 2, vec![vec![1.0], vec![1.0]]),
+            // Stereo averaged into mono
+            (2, 1, vec![vec![0.5, 0.5]]),
+            (2, 2, vec![vec![1.0, 0.0], vec![0.0, 1.0]]),
+            // 5.1 folded onto stereo in order
+            (
+                6,
+                2,
+                vec![
+                    vec![third, 0.0, third, 0.0, third, 0.0],
+                    vec![0.0, third, 0.0, third, 0.0, third],
+                ],
+            ),
+            // Stereo on the first two channels of 5.1
+            (
+                2,
+                6,
+                vec![
+                    vec![1.0, 0.0],
+                    vec![0.0, 1.0],
+                    vec![0.0, 0.0],
+                    vec![0.0, 0.0],
+                    vec![0.0, 0.0],
+                    vec![0.0, 0.0],
+                ],
+            ),
+            // Uneven folds average the channels each device channel gets
+            (3, 2, vec![vec![0.5, 0.0, 0.5], vec![0.0, 1.0, 0.0]]),
+        ];
+
+        for (inputs, outputs, expected) in cases {
+            assert_eq!(
+                gains(&ChannelMap::Auto, inputs, outputs),
+                expected,
+                "{} -> {} channels",
+                inputs,
+                outputs
+            );
+        }
+    }
+
+    #[test]
+    fn routes_map_gains() {
+        let cases = [
+            // Swapped stereo
+            (
+                vec![Some(1), Some(0)],
+                2,
+                2,
+                vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+            ),
+            // One stream channel on both device channels
+            (
+                vec![Some(0), Some(0)],
+                2,
+                2,
+                vec![vec![1.0, 0.0], vec![1.0, 0.0]],
+            ),
+            // Silenced and missing routes
+            (
+                vec![None, Some(0)],
+                2,
+                3,
+                vec![vec![0.0, 0.0], vec![1.0, 0.0], vec![0.0, 0.0]],
+            ),
+            // Routes to channels the stream does not have are silent
+            (
+                vec![Some(2), Some(1), Some(usize::MAX)],
+                2,
+                3,
+                vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![0.0, 0.0]],
+            ),
+            // Routes past the device channels are ignored
+            (vec![Some(1), Some(0), Some(0)], 2, 1, vec![vec![0.0, 1.0]]),
+        ];
+
+        for (routes, inputs, outputs, expected) in cases {
+            assert_eq!(
+                gains(&ChannelMap::Routes(routes.clone()), inputs, outputs),
+                expected,
+                "{:?} with {} -> {} channels",
+                routes,
+                inputs,
+                outputs
+            );
+        }
+    }
+
+    #[test]
+    fn mixes_scaled_samples() {
+        let mixer = ChannelMixer::new(&ChannelMap::Auto, 2, 1);
+        let mut output = Vec::new();
+        mixer.mix(&[0.5, -0.25], &mut output);
+        mixer.mix(&[1.0, 1.0], &mut output);
+        assert_eq!(output, [0.125, 1.0]);
+    }
+}
@@ -1,21 +1,51 @@
+mod concealment;
+mod convert;
+mod jitter;
+mod mixer;
+mod reorder;
 mod socket;
+mod source;
 mod stream;
+mod stream_name;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
-use crate::utils::errors::RustyVbanError;
+use std::net::SocketAddr;
 
+use crate::protocol::header::MAX_NUM_CHANNELS;
+use crate::utils::errors::RustyVbanError;
+use crate::utils::log;
+use crate::utils::net::{self, SocketOptions};
+pub use crate::utils::resample::ResampleQuality;
+
+pub use self::concealment::Concealment;
+pub use self::mixer::ChannelMap;
+pub use self::reorder::{PacketStats, MAX_REORDER_WINDOW};
+pub use self::socket::{FormatError, StreamFormat};
+pub use self::stream_name::StreamName;
 use self::{
     socket::{VbanReceptorSocket, VbanReceptorSocketBuilder},
+    source::Sources,
     stream::{VbanReceptorStream, VbanReceptorStreamBuilder},
 };
 
 pub struct ReceptorBuilder {
     latency: u32,
-    stream_name: Option<String>,
-    channels: u8,
-    ip_address: Option<String>,
+    stream_name: Option<StreamName>,
+    channels: Option<u16>,
+    channel_map: ChannelMap,
+    reorder_window: usize,
+    concealment: Concealment,
+    resample_quality: ResampleQuality,
+    auto_format: bool,
+    sources: Vec<String>,
+    any_source: bool,
+    source_port: Option<u16>,
+    multicast_groups: Vec<String>,
+    multicast_interface: Option<String>,
+    bind_address: Option<String>,
     port: u16,
+    socket_options: SocketOptions,
     device: String,
     device_type: String,
     backend: String,
@@ -23,46 +53,178 @@ pub struct ReceptorBuilder {
 
 pub struct ReceptorParams {
     latency: u32,
-    stream_name: String,
-    channels: u8,
-    ip_address: String,
+    stream_name: StreamName,
+    channels: Option<u16>,
+    channel_map: ChannelMap,
+    reorder_window: usize,
+    concealment: Concealment,
+    resample_quality: ResampleQuality,
+    auto_format: bool,
+    format: Option<StreamFormat>,
+    sources: Sources,
+    source_port: Option<u16>,
+    multicast_groups: Vec<String>,
+    multicast_interface: Option<String>,
+    bind_addr: Option<SocketAddr>,
     port: u16,
+    socket_options: SocketOptions,
     device: String,
     device_type: String,
     backend: String,
 }
 
-impl ReceptorBuilder {
-    pub fn default() -> Self {
+impl Default for ReceptorBuilder {
+    fn default() -> Self {
         Self {
             latency: 16,
             stream_name: None,
-            channels: 2,
-            ip_address: None,
+            channels: None,
+            channel_map: ChannelMap::default(),
+            reorder_window: 3,
+            concealment: Concealment::default(),
+            resample_quality: ResampleQuality::default(),
+            auto_format: false,
+            sources: Vec::new(),
+            any_source: false,
+            source_port: None,
+            multicast_groups: Vec::new(),
+            multicast_interface: None,
+            bind_address: None,
             port: 6980,
+            socket_options: SocketOptions::default(),
             device: "default".to_string(),
             device_type: "output".to_string(),
             backend: "default".to_string(),
         }
     }
+}
 
+impl ReceptorBuilder {
     pub fn latency(mut self, latency: u32) -> Self {
         self.latency = latency;
         self
     }
 
+    /// Play the stream with this name, ignoring ASCII case and trailing
+    /// spaces.
     pub fn stream_name<T: Into<String>>(mut self, stream_name: T) -> Self {
-        self.stream_name = Some(stream_name.into());
+        self.stream_name = Some(StreamName::Exact(stream_name.into()));
+        self
+    }
+
+    /// Play the stream whose name matches a glob such as `Mic*`. When several
+    /// match, the first one received plays until it stops.
+    pub fn stream_name_glob<T: Into<String>>(mut self, glob: T) -> Self {
+        self.stream_name = Some(StreamName::Glob(glob.into()));
+        self
+    }
+
+    /// Play the stream whose name matches a regular expression. When several
+    /// match, the first one received plays until it stops.
+    pub fn stream_name_regex<T: Into<String>>(mut self, regex: T) -> Self {
+        self.stream_name = Some(StreamName::Regex(regex.into()));
+        self
+    }
+
+    /// Play the first audio stream received, whatever its name.
+    pub fn first_stream(mut self) -> Self {
+        self.stream_name = Some(StreamName::First);
+        self
+    }
+
+    /// Only accept streams with this many channels. Streams with any channel
+    /// count are accepted by default and mixed to the device channels, see
+    /// `ChannelMap`.
+    pub fn channels(mut self, channels: u16) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    /// How stream channels are routed to the device channels.
+    pub fn channel_map(mut self, channel_map: ChannelMap) -> Self {
+        self.channel_map = channel_map;
+        self
+    }
+
+    /// Number of packets held back to put out of order packets back in
+    /// sequence. Larger windows tolerate more reordering at the cost of
+    /// latency. Must be between 1 and `MAX_REORDER_WINDOW`.
+    pub fn reorder_window(mut self, reorder_window: usize) -> Self {
+        self.reorder_window = reorder_window;
+        self
+    }
+
+    /// How audio lost on the network or to an underrun is filled in.
+    pub fn concealment(mut self, concealment: Concealment) -> Self {
+        self.concealment = concealment;
         self
     }
 
-    pub fn channels(mut self, channels: u8) -> Self {
-        self.channels = channels;
+    /// Interpolation used when the stream sample rate differs from the
+    /// output device rate.
+    pub fn resample_quality(mut self, resample_quality: ResampleQuality) -> Self {
+        self.resample_quality = resample_quality;
         self
     }
 
+    /// Reopens the output device with the channels and sample rate of the
+    /// incoming stream once its first packet arrives, and again whenever the
+    /// sender changes them. Only applied by `Receptor::run`, receptors
+    /// driven with `play` keep the output format they were built with.
+    pub fn auto_format(mut self, auto_format: bool) -> Self {
+        self.auto_format = auto_format;
+        self
+    }
+
+    /// Accept packets sent from this address, IPv4 or IPv6, or from this
+    /// hostname, along with any other source allowed. Hostnames are resolved
+    /// again every 30 seconds, following hosts whose address changes.
     pub fn ip_address<T: Into<String>>(mut self, ip_address: T) -> Self {
-        self.ip_address = Some(ip_address.into());
+        self.sources.push(ip_address.into());
+        self
+    }
+
+    /// Also accept packets sent from an address, a hostname or a CIDR range
+    /// such as `10.0.0.0/24`.
+    pub fn allow_source<T: Into<String>>(mut self, source: T) -> Self {
+        self.sources.push(source.into());
+        self
+    }
+
+    /// Accept packets sent from any address.
+    pub fn any_source(mut self) -> Self {
+        self.any_source = true;
+        self
+    }
+
+    /// Only accept packets sent from this port.
+    pub fn source_port(mut self, source_port: u16) -> Self {
+        self.source_port = Some(source_port);
+        self
+    }
+
+    /// Joins a multicast group such as `239.0.0.1` or `ff15::1` to receive
+    /// the streams sent to it. IPv6 link-local groups take a scope such as
+    /// `ff12::1%eth0`. Can be called once per group, the groups being all
+    /// IPv4 or all IPv6.
+    pub fn multicast_group<T: Into<String>>(mut self, multicast_group: T) -> Self {
+        self.multicast_groups.push(multicast_group.into());
+        self
+    }
+
+    /// Local interface multicast groups are joined on, given by address for
+    /// IPv4 groups and by name or index such as `eth0` for IPv6 ones.
+    /// Defaults to the one picked by the system.
+    pub fn multicast_interface<T: Into<String>>(mut self, multicast_interface: T) -> Self {
+        self.multicast_interface = Some(multicast_interface.into());
+        self
+    }
+
+    /// Address of the local interface the receptor listens on, such as
+    /// `192.168.0.10` or `fe80::1%eth0`. Defaults to every interface, over
+    /// both IPv6 and IPv4 when the system supports it.
+    pub fn bind_address<T: Into<String>>(mut self, bind_address: T) -> Self {
+        self.bind_address = Some(bind_address.into());
         self
     }
 
@@ -71,6 +233,34 @@ impl ReceptorBuilder {
         self
     }
 
+    /// Lets other sockets bind the same address and port, with
+    /// `SO_REUSEADDR`.
+    pub fn reuse_address(mut self, reuse_address: bool) -> Self {
+        self.socket_options.reuse_address = reuse_address;
+        self
+    }
+
+    /// Lets other processes listen on the same port, with `SO_REUSEPORT`.
+    /// Only available on Unix.
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.socket_options.reuse_port = reuse_port;
+        self
+    }
+
+    /// Only receive IPv6 packets when listening on every interface or on an
+    /// IPv6 address, rather than both IPv6 and IPv4 ones.
+    pub fn ipv6_only(mut self, ipv6_only: bool) -> Self {
+        self.socket_options.ipv6_only = ipv6_only;
+        self
+    }
+
+    /// Size of the socket receive buffer, in bytes. A larger buffer absorbs
+    /// longer bursts of packets.
+    pub fn receive_buffer_size(mut self, receive_buffer_size: usize) -> Self {
+        self.socket_options.receive_buffer_size = Some(receive_buffer_size);
+        self
+    }
+
     pub fn device<T: Into<String>>(mut self, device: T) -> Self {
         self.device = device.into();
         self
@@ -90,8 +280,40 @@ impl ReceptorBuilder {
         let latency = self.latency;
         let stream_name = self.stream_name.context("Stream name is required")?;
         let channels = self.channels;
-        let ip_address = self.ip_address.context("IP address is required")?;
+        if channels.is_some_and(|channels| !(1..=MAX_NUM_CHANNELS as u16).contains(&channels)) {
+            return Err(anyhow!(
+                "Channels must be between 1 and {}",
+                MAX_NUM_CHANNELS
+            ));
+        }
+        let channel_map = self.channel_map;
+        let reorder_window = self.reorder_window;
+        if !(1..=MAX_REORDER_WINDOW).contains(&reorder_window) {
+            return Err(anyhow!(
+                "Reorder window must be between 1 and {}",
+                MAX_REORDER_WINDOW
+            ));
+        }
+        let concealment = self.concealment;
+        let resample_quality = self.resample_quality;
+        let auto_format = self.auto_format;
+        let sources = if self.any_source {
+            Sources::Any
+        } else if self.sources.is_empty() {
+            return Err(anyhow!("IP address is required, or allow any source"));
+        } else {
+            Sources::parse(&self.sources)?
+        };
+        let source_port = self.source_port;
+        let multicast_groups = self.multicast_groups;
+        let multicast_interface = self.multicast_interface;
+        let bind_addr = self
+            .bind_address
+            .map(|address| net::parse_socket_addr(&address, self.port))
+            .transpose()
+            .context("Invalid bind address")?;
         let port = self.port;
+        let socket_options = self.socket_options;
         let device = self.device;
         let device_type = self.device_type;
         let backend = self.backend;
@@ -101,13 +323,24 @@ impl ReceptorBuilder {
             .device_type(&device_type)
             .host_name(&backend)
             .latency(latency as f32)
+            .concealment(concealment)
             .build()?;
 
         let socket = VbanReceptorSocketBuilder::default()
-            .port(self.port)
-            .incoming_addr(&ip_address)
-            .incoming_stream_name(&stream_name)
-            .channels(self.channels)
+            .bind_addr(bind_addr)
+            .port(port)
+            .socket_options(socket_options.clone())
+            .sources(sources.clone())
+            .source_port(source_port)
+            .multicast_groups(multicast_groups.clone())
+            .multicast_interface(multicast_interface.clone())
+            .stream_name(stream_name.clone())
+            .channels(channels)
+            .channel_map(channel_map.clone())
+            .output_channels(stream.channels())
+            .reorder_window(reorder_window)
+            .output_sample_rate(stream.sample_rate())
+            .resample_quality(resample_quality)
             .producer(producer)
             .build()?;
 
@@ -115,8 +348,19 @@ impl ReceptorBuilder {
             latency,
             stream_name,
             channels,
-            ip_address,
+            channel_map,
+            reorder_window,
+            concealment,
+            resample_quality,
+            auto_format,
+            format: None,
+            sources,
+            source_port,
+            multicast_groups,
+            multicast_interface,
+            bind_addr,
             port,
+            socket_options,
             device,
             device_type,
             backend,
@@ -136,21 +380,22 @@ pub struct Receptor {
     params: ReceptorParams,
 }
 
+#[derive(Default)]
 pub struct ReceptorOptions {
     pub retry: bool,
 }
 
-impl Default for ReceptorOptions {
-    fn default() -> Self {
-        Self { retry: false }
-    }
-}
-
 impl Receptor {
     pub fn run(mut self, options: ReceptorOptions) -> Result<Self, RustyVbanError> {
         self.play()?;
 
         while self.stream.should_run(&self.params.device) {
+            if let Some(format) = self.changed_format() {
+                log::info(&format!("Stream format changed to {:?}", format));
+                self.params.format = Some(format);
+                self = self.rebuild()?;
+                self.play()?;
+            }
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
 
@@ -177,6 +422,57 @@ impl Receptor {
         self.stream.should_run(&self.params.device)
     }
 
+    /// Audio currently buffered between the network and the output device.
+    pub fn latency(&self) -> std::time::Duration {
+        self.stream.jitter_stats().latency()
+    }
+
+    /// Estimated clock drift between the sender and the output device, in
+    /// parts per million.
+    pub fn drift_ppm(&self) -> f64 {
+        self.stream.jitter_stats().drift_ppm()
+    }
+
+    /// Lost, reordered, duplicate and late packet counts.
+    pub fn packet_stats(&self) -> &PacketStats {
+        self.socket.packet_stats()
+    }
+
+    /// The name of the stream currently played, if any.
+    pub fn stream_name(&self) -> Option<String> {
+        self.socket.accepted_stream_name()
+    }
+
+    /// The format of the stream currently played, if any.
+    pub fn stream_format(&self) -> Option<StreamFormat> {
+        self.socket.stream_format()
+    }
+
+    /// The sender currently played, if any.
+    pub fn locked_source(&self) -> Option<SocketAddr> {
+        self.socket.locked_source()
+    }
+
+    /// The incoming format that could not be played, if any.
+    pub fn format_error(&self) -> Option<FormatError> {
+        self.socket.format_error()
+    }
+
+    /// The incoming format when its sample rate or channels differ from the
+    /// ones the output was opened for in auto format mode. Bit resolution
+    /// changes are decoded without reopening the output.
+    fn changed_format(&self) -> Option<StreamFormat> {
+        if !self.params.auto_format {
+            return None;
+        }
+
+        self.socket.stream_format().filter(|format| {
+            self.params.format.is_none_or(|opened| {
+                format.sample_rate != opened.sample_rate || format.channels != opened.channels
+            })
+        })
+    }
+
     pub fn rebuild(self) -> Result<Self> {
         let Self {
             params,
@@ -192,13 +488,25 @@ impl Receptor {
             .device_type(&params.device_type)
             .host_name(&params.backend)
             .latency(params.latency as f32)
+            .concealment(params.concealment)
+            .format(params.format)
             .build()?;
 
         let socket = VbanReceptorSocketBuilder::default()
+            .bind_addr(params.bind_addr)
             .port(params.port)
-            .incoming_addr(&params.ip_address)
-            .incoming_stream_name(&params.stream_name)
+            .socket_options(params.socket_options.clone())
+            .sources(params.sources.clone())
+            .source_port(params.source_port)
+            .multicast_groups(params.multicast_groups.clone())
+            .multicast_interface(params.multicast_interface.clone())
+            .stream_name(params.stream_name.clone())
             .channels(params.channels)
+            .channel_map(params.channel_map.clone())
+            .output_channels(stream.channels())
+            .reorder_window(params.reorder_window)
+            .output_sample_rate(stream.sample_rate())
+            .resample_quality(params.resample_quality)
             .producer(producer)
             .build()?;
 
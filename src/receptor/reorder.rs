@@ -0,0 +1,343 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::protocol::packet::{PacketRef, MAX_PACKET_SIZE};
+
+use super::convert::Converter;
+use super::stream::MISSING_SAMPLE;
+
+/// Frame number distance past which a packet ahead of the stream is assumed
+/// to follow a restart of the sender counter, so the stream is resynchronized
+/// instead of filling the gap.
+const MAX_GAP: u32 = 64;
+/// Packets in a row further than `MAX_GAP` behind the stream after which the
+/// sender is assumed to have restarted its counter lower. Fewer are dropped
+/// as late.
+const MAX_OUT_OF_RANGE: u32 = 4;
+/// Largest reorder window, packets further ahead resynchronizing the stream.
+pub const MAX_REORDER_WINDOW: usize = MAX_GAP as usize;
+
+/// Packet counters of the receive path.
+#[derive(Default)]
+pub struct PacketStats {
+    lost: AtomicU64,
+    reordered: AtomicU64,
+    duplicates: AtomicU64,
+    late: AtomicU64,
+}
+
+impl PacketStats {
+    /// Packets that never arrived and were replaced by missing audio.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    /// Packets that arrived out of order but in time to be played in order.
+    pub fn reordered(&self) -> u64 {
+        self.reordered.load(Ordering::Relaxed)
+    }
+
+    /// Packets received more than once.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// Packets that arrived after their place in the stream was given up.
+    pub fn late(&self) -> u64 {
+        self.late.load(Ordering::Relaxed)
+    }
+}
+
+struct Slot {
+    frame_number: Option<u32>,
+    samples: Vec<f64>,
+}
+
+/// Puts packets back in frame number order within a window of packets,
+/// dropping duplicates and late packets and filling lost packets with
+/// `MISSING_SAMPLE` so the stream keeps its timing.
+pub struct Reorder {
+    window: u32,
+    next: Option<u32>,
+    packet_len: usize,
+    // Bit `k` is set when frame `next - 1 - k` was played
+    played: u64,
+    out_of_range: u32,
+    slots: Vec<Slot>,
+    stats: Arc<PacketStats>,
+}
+
+impl Reorder {
+    pub fn new(window: usize, stats: Arc<PacketStats>) -> Self {
+        let window = window.max(1);
+
+        Self {
+            window: window as u32,
+            next: None,
+            packet_len: 0,
+            played: 0,
+            out_of_range: 0,
+            slots: (0..window)
+                .map(|_| Slot {
+                    frame_number: None,
+                    samples: Vec::with_capacity(MAX_PACKET_SIZE),
+                })
+                .collect(),
+            stats,
+        }
+    }
+
+    pub fn push(&mut self, packet: &PacketRef, output: &mut Converter) {
+        let frame_number = packet.header().frame_number();
+        let distance = self.distance(frame_number);
+        if self.next.is_none() || distance > MAX_GAP as i32 {
+            self.resync(frame_number);
+        } else if distance < -(MAX_GAP as i32) {
+            self.out_of_range += 1;
+            if self.out_of_range < MAX_OUT_OF_RANGE {
+                self.stats.late.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            self.resync(frame_number);
+        }
+        self.out_of_range = 0;
+        self.packet_len = packet.samples::<f64>().len();
+        output.set_input_format(
+            packet.header().sample_rate().hz(),
+            packet.header().num_channels() as usize,
+        );
+
+        let distance = self.distance(frame_number);
+        if distance < 0 {
+            let age = (-distance - 1) as u32;
+            if age < u64::BITS && self.played & (1 << age) != 0 {
+                self.stats.duplicates.fetch_add(1, Ordering::Relaxed);
+            } else {
+                self.stats.late.fetch_add(1, Ordering::Relaxed);
+            }
+            return;
+        }
+
+        // Give up on the oldest missing packets until this one fits the window
+        while self.distance(frame_number) >= self.window as i32 {
+            self.skip(output);
+        }
+
+        if Some(frame_number) == self.next {
+            if self.slots.iter().any(|slot| slot.frame_number.is_some()) {
+                self.stats.reordered.fetch_add(1, Ordering::Relaxed);
+            }
+            output.push(&mut packet.samples::<f64>());
+            self.advance(true);
+            self.flush(output);
+        } else {
+            let slot = &mut self.slots[(frame_number % self.window) as usize];
+            if slot.frame_number == Some(frame_number) {
+                self.stats.duplicates.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            slot.frame_number = Some(frame_number);
+            slot.samples.clear();
+            slot.samples.extend(packet.samples::<f64>());
+        }
+    }
+
+    /// Forgets the stream position, so the next packet starts a new stream.
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+
+    /// Signed distance from the next frame to play, negative for frames
+    /// already played or given up on.
+    fn distance(&self, frame_number: u32) -> i32 {
+        frame_number.wrapping_sub(self.next.unwrap_or(frame_number)) as i32
+    }
+
+    fn resync(&mut self, frame_number: u32) {
+        self.next = Some(frame_number);
+        self.played = 0;
+        for slot in self.slots.iter_mut() {
+            slot.frame_number = None;
+        }
+    }
+
+    /// Plays the next frame from its slot, or as missing audio when it never
+    /// arrived.
+    fn skip(&mut self, output: &mut Converter) {
+        if !self.pop_slot(output) {
+            self.stats.lost.fetch_add(1, Ordering::Relaxed);
+            output.push(&mut std::iter::repeat_n(MISSING_SAMPLE, self.packet_len));
+            self.advance(false);
+        }
+        self.flush(output);
+    }
+
+    /// Plays every buffered packet that directly follows the stream.
+    fn flush(&mut self, output: &mut Converter) {
+        while self.pop_slot(output) {}
+    }
+
+    fn pop_slot(&mut self, output: &mut Converter) -> bool {
+        let Some(next) = self.next else {
+            return false;
+        };
+        let slot = &mut self.slots[(next % self.window) as usize];
+        if slot.frame_number != Some(next) {
+            return false;
+        }
+        slot.frame_number = None;
+        output.push(&mut slot.samples.iter().copied());
+        self.advance(true);
+
+        true
+    }
+
+    fn advance(&mut self, played: bool) {
+        self.played = (self.played << 1) | played as u64;
+        self.next = self.next.map(|next| next.wrapping_add(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ringbuf::HeapRb;
+
+    use crate::protocol::{codec, header::Header, packet::Packet};
+    use crate::receptor::mixer::ChannelMap;
+    use crate::receptor::stream::VbanStreamConsumer;
+    use crate::utils::resample::ResampleQuality;
+
+    /// Single sample packets carrying their frame number.
+    fn packet(frame_number: u32) -> Packet {
+        let mut header = Header::new("Stream1");
+        header.set_num_samples(1);
+        header.set_num_channels(1);
+        header.set_frame_number(frame_number);
+        let mut data = vec![0; header.payload_size()];
+        let value = (frame_number % 1024) as f64 / 1024.0;
+        codec::encode(header.bit_resolution(), &[value], &mut data).unwrap();
+        Packet::new(header, data)
+    }
+
+    struct Receiver {
+        reorder: Reorder,
+        converter: Converter,
+        consumer: VbanStreamConsumer,
+        stats: Arc<PacketStats>,
+    }
+
+    impl Receiver {
+        fn new(window: usize) -> Self {
+            let stats = Arc::new(PacketStats::default());
+            let (producer, consumer) = HeapRb::<f64>::new(1 << 12).split();
+            Self {
+                reorder: Reorder::new(window, stats.clone()),
+                converter: Converter::new(
+                    ResampleQuality::default(),
+                    ChannelMap::Auto,
+                    48_000,
+                    1,
+                    producer,
+                ),
+                consumer,
+                stats,
+            }
+        }
+
+        /// Pushes packets with the given frame numbers, returning the frame
+        /// numbers played, modulo 1024, with `None` for missing audio.
+        fn receive(&mut self, frame_numbers: impl IntoIterator<Item = u32>) -> Vec<Option<u32>> {
+            for frame_number in frame_numbers {
+                self.reorder
+                    .push(&packet(frame_number).as_packet_ref(), &mut self.converter);
+            }
+            self.consumer
+                .pop_iter()
+                .map(|sample| (!sample.is_nan()).then_some((sample * 1024.0) as u32))
+                .collect()
+        }
+    }
+
+    fn played(frame_numbers: impl IntoIterator<Item = u32>) -> Vec<Option<u32>> {
+        frame_numbers
+            .into_iter()
+            .map(|frame_number| Some(frame_number % 1024))
+            .collect()
+    }
+
+    #[test]
+    fn reorders_within_the_window() {
+        let mut receiver = Receiver::new(3);
+        assert_eq!(receiver.receive([0, 2, 1, 3, 5, 4, 6]), played(0..=6));
+        assert_eq!(receiver.stats.reordered(), 2);
+        assert_eq!(receiver.stats.lost(), 0);
+    }
+
+    #[test]
+    fn drops_duplicates() {
+        let mut receiver = Receiver::new(3);
+        assert_eq!(receiver.receive([0, 1, 1, 3, 3, 2, 0]), played(0..=3));
+        assert_eq!(receiver.stats.duplicates(), 3);
+        assert_eq!(receiver.stats.late(), 0);
+    }
+
+    #[test]
+    fn fills_lost_packets() {
+        let mut receiver = Receiver::new(2);
+        assert_eq!(
+            receiver.receive([0, 1, 4, 5]),
+            [Some(0), Some(1), None, None, Some(4), Some(5)]
+        );
+        assert_eq!(receiver.stats.lost(), 2);
+
+        // A lost packet arriving after its place was given up on is late
+        assert_eq!(receiver.receive([3, 6]), played([6]));
+        assert_eq!(receiver.stats.late(), 1);
+    }
+
+    #[test]
+    fn wraps_the_frame_counter() {
+        let mut receiver = Receiver::new(3);
+        assert_eq!(
+            receiver.receive([u32::MAX - 1, 0, u32::MAX, 1, 3, 2]),
+            played([u32::MAX - 1, u32::MAX, 0, 1, 2, 3])
+        );
+        assert_eq!(receiver.stats.reordered(), 2);
+        assert_eq!(receiver.stats.lost(), 0);
+    }
+
+    #[test]
+    fn drops_very_late_packets() {
+        let mut receiver = Receiver::new(3);
+        assert_eq!(receiver.receive(100..=200), played(100..=200));
+
+        // Packets far in the past are dropped rather than restarting the
+        // stream
+        assert_eq!(receiver.receive([10, 201, 20, 202]), played(201..=202));
+        assert_eq!(receiver.stats.late(), 2);
+        assert_eq!(receiver.stats.lost(), 0);
+    }
+
+    #[test]
+    fn resyncs_on_counter_restarts() {
+        let mut receiver = Receiver::new(3);
+        assert_eq!(receiver.receive(100..=200), played(100..=200));
+
+        // A jump ahead restarts the stream right away
+        assert_eq!(receiver.receive(1000..=1001), played(1000..=1001));
+        assert_eq!(receiver.stats.lost(), 0);
+
+        // A restart lower does after a few packets in a row
+        let restart = 0..MAX_OUT_OF_RANGE + 2;
+        assert_eq!(
+            receiver.receive(restart.clone()),
+            played(restart.skip(MAX_OUT_OF_RANGE as usize - 1))
+        );
+        assert_eq!(receiver.stats.late(), MAX_OUT_OF_RANGE as u64 - 1);
+        assert_eq!(receiver.stats.lost(), 0);
+    }
+}
@@ -1,35 +1,53 @@
 use anyhow::{anyhow, Context, Result};
 
-use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-use byteorder::{ByteOrder, LittleEndian};
-
-use crate::protocol::header::{Codec, SubProtocol};
-use crate::protocol::packet::{Packet, MAX_PACKET_SIZE};
+use crate::protocol::header::{BitResolution, Codec, Header, SampleRate, SubProtocol};
+use crate::protocol::packet::{PacketRef, MAX_PACKET_SIZE};
 use crate::utils::log;
-
+use crate::utils::net::{self, Interface, SocketOptions};
+use crate::utils::resample::ResampleQuality;
+use crate::utils::resolve::Resolver;
+
+use super::convert::Converter;
+use super::mixer::ChannelMap;
+use super::reorder::{PacketStats, Reorder};
+use super::source::{SourceLock, Sources};
 use super::stream::VbanStreamProducer;
+use super::stream_name::{StreamName, StreamNameMatcher};
 
+/// How long a receive call blocks before checking whether the socket was
+/// stopped, bounding the time `stop` waits for the receive thread.
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
 pub struct VbanReceptorSocketBuilder {
+    bind_addr: Option<SocketAddr>,
     port: Option<u16>,
-    incoming_addr: Option<String>,
-    incoming_stream_name: Option<String>,
-    channels: Option<u8>,
+    socket_options: Option<SocketOptions>,
+    sources: Option<Sources>,
+    source_port: Option<u16>,
+    multicast_groups: Vec<String>,
+    multicast_interface: Option<String>,
+    stream_name: Option<StreamName>,
+    channels: Option<u16>,
+    reorder_window: Option<usize>,
+    output_sample_rate: Option<u32>,
+    output_channels: Option<usize>,
+    resample_quality: Option<ResampleQuality>,
+    channel_map: Option<ChannelMap>,
     producer: Option<VbanStreamProducer>,
 }
 
 impl VbanReceptorSocketBuilder {
-    pub fn default() -> Self {
-        Self {
-            port: None,
-            incoming_addr: None,
-            incoming_stream_name: None,
-            channels: None,
-            producer: None,
-        }
+    pub fn bind_addr(mut self, bind_addr: Option<SocketAddr>) -> Self {
+        self.bind_addr = bind_addr;
+        self
     }
 
     pub fn port(mut self, port: u16) -> Self {
@@ -37,18 +55,63 @@ impl VbanReceptorSocketBuilder {
         self
     }
 
-    pub fn incoming_addr(mut self, incoming_addr: &str) -> Self {
-        self.incoming_addr = Some(incoming_addr.to_string());
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = Some(socket_options);
+        self
+    }
+
+    pub fn sources(mut self, sources: Sources) -> Self {
+        self.sources = Some(sources);
+        self
+    }
+
+    pub fn source_port(mut self, source_port: Option<u16>) -> Self {
+        self.source_port = source_port;
+        self
+    }
+
+    pub fn multicast_groups(mut self, multicast_groups: Vec<String>) -> Self {
+        self.multicast_groups = multicast_groups;
+        self
+    }
+
+    pub fn multicast_interface(mut self, multicast_interface: Option<String>) -> Self {
+        self.multicast_interface = multicast_interface;
+        self
+    }
+
+    pub fn stream_name(mut self, stream_name: StreamName) -> Self {
+        self.stream_name = Some(stream_name);
+        self
+    }
+
+    pub fn channels(mut self, channels: Option<u16>) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn reorder_window(mut self, reorder_window: usize) -> Self {
+        self.reorder_window = Some(reorder_window);
+        self
+    }
+
+    pub fn output_sample_rate(mut self, output_sample_rate: u32) -> Self {
+        self.output_sample_rate = Some(output_sample_rate);
+        self
+    }
+
+    pub fn output_channels(mut self, output_channels: usize) -> Self {
+        self.output_channels = Some(output_channels);
         self
     }
 
-    pub fn incoming_stream_name(mut self, incoming_stream_name: &str) -> Self {
-        self.incoming_stream_name = Some(incoming_stream_name.to_string());
+    pub fn channel_map(mut self, channel_map: ChannelMap) -> Self {
+        self.channel_map = Some(channel_map);
         self
     }
 
-    pub fn channels(mut self, channels: u8) -> Self {
-        self.channels = Some(channels);
+    pub fn resample_quality(mut self, resample_quality: ResampleQuality) -> Self {
+        self.resample_quality = Some(resample_quality);
         self
     }
 
@@ -59,21 +122,68 @@ impl VbanReceptorSocketBuilder {
 
     pub fn build(self) -> Result<VbanReceptorSocket> {
         let port = self.port.context("Port is required")?;
-        let incoming_addr = self
-            .incoming_addr
-            .context("Incomming address is required")?;
-        let incoming_stream_name = self
-            .incoming_stream_name
-            .context("Incomming stream name is required")?;
-        let channels = self.channels.context("Channels is required")?;
+        let socket_options = self.socket_options.context("Socket options are required")?;
+        let sources = self.sources.context("Sources are required")?;
+        let source_port = self.source_port;
+        let stream_name = self.stream_name.context("Stream name is required")?;
+        let accepted_stream_name = Arc::new(Mutex::new(None));
+        // Fail early on an invalid pattern rather than when starting
+        StreamNameMatcher::new(&stream_name, accepted_stream_name.clone())?;
+        let channels = self.channels;
+        let reorder_window = self.reorder_window.context("Reorder window is required")?;
+        let output_sample_rate = self
+            .output_sample_rate
+            .context("Output sample rate is required")?;
+        let output_channels = self
+            .output_channels
+            .context("Output channels is required")?;
+        let resample_quality = self
+            .resample_quality
+            .context("Resample quality is required")?;
+        let channel_map = self.channel_map.context("Channel map is required")?;
         let producer = self.producer.context("Producer is required")?;
-        let addr = SocketAddr::new("0.0.0.0".parse()?, port);
-        let socket = UdpSocket::bind(addr)?;
+        let multicast_groups = self
+            .multicast_groups
+            .iter()
+            .map(|group| {
+                net::parse_scoped_ip(group)
+                    .with_context(|| format!("Invalid multicast group {}", group))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let multicast_interface = self
+            .multicast_interface
+            .map(|interface| Interface::parse(&interface))
+            .transpose()
+            .context("Invalid multicast interface")?;
+        let socket = match self.bind_addr {
+            Some(addr) => net::bind_udp(addr, &socket_options)?,
+            None => bind_any(port, &multicast_groups, &socket_options)?,
+        };
+        socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+        for (group, scope_id) in multicast_groups {
+            join_multicast(&socket, group, scope_id, multicast_interface)?;
+        }
+        let resolver = sources.has_hosts().then(|| {
+            let sources = sources.clone();
+            Resolver::spawn(move || sources.refresh())
+        });
 
         Ok(VbanReceptorSocket {
             socket: Arc::new(socket),
-            incoming_addr: incoming_addr.parse()?,
-            incoming_stream_name,
+            format_error: Arc::new(Mutex::new(None)),
+            packet_stats: Arc::new(PacketStats::default()),
+            reorder_window,
+            output_sample_rate,
+            output_channels,
+            resample_quality,
+            channel_map,
+            sources,
+            _resolver: resolver,
+            source_port,
+            locked_source: Arc::new(Mutex::new(None)),
+            stream_name,
+            accepted_stream_name,
+            stream_format: Arc::new(Mutex::new(None)),
             channels,
             producer: Some(producer),
             player_handle: None,
@@ -82,11 +192,70 @@ impl VbanReceptorSocketBuilder {
     }
 }
 
+/// An incoming stream format the receptor cannot play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatError {
+    UnsupportedCodec(Codec),
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FormatError::UnsupportedCodec(codec) => write!(f, "Unsupported codec {:?}", codec),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Packets of a stream whose name does not match, which the matcher logs
+/// once per stream.
+#[derive(Debug)]
+struct IgnoredStream;
+
+impl std::fmt::Display for IgnoredStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Stream name does not match")
+    }
+}
+
+impl std::error::Error for IgnoredStream {}
+
+/// Audio format of an incoming stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub sample_rate: SampleRate,
+    pub channels: u16,
+    pub bit_resolution: BitResolution,
+}
+
+impl From<&Header> for StreamFormat {
+    fn from(header: &Header) -> Self {
+        Self {
+            sample_rate: header.sample_rate(),
+            channels: header.num_channels(),
+            bit_resolution: header.bit_resolution(),
+        }
+    }
+}
+
 pub struct VbanReceptorSocket {
     socket: Arc<UdpSocket>,
-    incoming_addr: IpAddr,
-    incoming_stream_name: String,
-    channels: u8,
+    format_error: Arc<Mutex<Option<FormatError>>>,
+    packet_stats: Arc<PacketStats>,
+    reorder_window: usize,
+    output_sample_rate: u32,
+    output_channels: usize,
+    resample_quality: ResampleQuality,
+    channel_map: ChannelMap,
+    sources: Sources,
+    _resolver: Option<Resolver>,
+    source_port: Option<u16>,
+    locked_source: Arc<Mutex<Option<SocketAddr>>>,
+    stream_name: StreamName,
+    accepted_stream_name: Arc<Mutex<Option<String>>>,
+    stream_format: Arc<Mutex<Option<StreamFormat>>>,
+    channels: Option<u16>,
     producer: Option<VbanStreamProducer>,
     player_handle: Option<std::thread::JoinHandle<Result<VbanStreamProducer>>>,
     player_running: Option<Arc<AtomicBool>>,
@@ -97,37 +266,88 @@ impl VbanReceptorSocket {
         let player_running = Arc::new(AtomicBool::new(true));
         let player_running_clone = player_running.clone();
         let socket = self.socket.clone();
-        let incoming_addr = self.incoming_addr.clone();
-        let incoming_stream_name = self.incoming_stream_name.clone();
+        let sources = self.sources.clone();
+        let source_port = self.source_port;
+        let mut stream_name =
+            StreamNameMatcher::new(&self.stream_name, self.accepted_stream_name.clone())?;
         let channels = self.channels;
-        let mut producer = self
+        let format_error = self.format_error.clone();
+        let stream_format = self.stream_format.clone();
+        let producer = self
             .producer
             .take()
             .ok_or(anyhow!("No producer available"))?;
 
+        let mut source_lock = SourceLock::new(self.locked_source.clone());
+        let mut reorder = Reorder::new(self.reorder_window, self.packet_stats.clone());
+        let mut converter = Converter::new(
+            self.resample_quality,
+            self.channel_map.clone(),
+            self.output_sample_rate,
+            self.output_channels,
+            producer,
+        );
+
         let player_handle = thread::spawn(move || {
             let mut buf = [0; MAX_PACKET_SIZE];
+            let mut last_warning = None;
+            let mut last_format = None;
 
             while player_running_clone.load(std::sync::atomic::Ordering::Relaxed) {
                 let packet = Self::receive_packet(
                     socket.clone(),
-                    &incoming_addr,
-                    &incoming_stream_name,
-                    &channels,
+                    &sources,
+                    source_port,
+                    &mut stream_name,
+                    channels,
                     &mut buf,
                 );
+                // Checked whatever was received, as packets of streams that
+                // are ignored would otherwise keep a silent one locked
+                if source_lock.expire() {
+                    stream_name.release();
+                }
                 match packet {
-                    Ok(packet) => {
-                        for sample in packet.data.chunks_exact(2) {
-                            let sample = LittleEndian::read_i16(&sample);
-                            producer.push(sample).ok();
+                    Ok(Some((packet, src))) => {
+                        let previous = source_lock.current();
+                        if !source_lock.accept(src) {
+                            continue;
+                        }
+                        if previous != Some(src) {
+                            reorder.reset();
+                        }
+                        stream_name.accept(packet.header().stream_name_str());
+                        let format = Some(StreamFormat::from(packet.header()));
+                        if format != last_format {
+                            *stream_format.lock().unwrap() = format;
+                            last_format = format;
                         }
+                        reorder.push(&packet, &mut converter);
+                        format_error.lock().unwrap().take();
                     }
-                    Err(e) => log::warn(&e.to_string()),
+                    Ok(None) => {}
+                    Err(e) if e.is::<IgnoredStream>() => {}
+                    Err(e) => match e.downcast::<FormatError>() {
+                        Ok(e) => {
+                            let mut format_error = format_error.lock().unwrap();
+                            if *format_error != Some(e) {
+                                log::warn(&e.to_string());
+                                *format_error = Some(e);
+                            }
+                        }
+                        Err(e) => {
+                            // Log each reason to drop packets once rather than per packet
+                            let warning = e.to_string();
+                            if last_warning.as_ref() != Some(&warning) {
+                                log::warn(&warning);
+                                last_warning = Some(warning);
+                            }
+                        }
+                    },
                 }
             }
 
-            Ok(producer)
+            Ok(converter.into_producer())
         });
         self.player_handle = Some(player_handle);
         self.player_running = Some(player_running);
@@ -158,25 +378,55 @@ impl VbanReceptorSocket {
         Ok(())
     }
 
-    fn receive_packet(
+    /// The last incoming format that could not be played, cleared as soon as
+    /// a playable packet arrives.
+    pub fn format_error(&self) -> Option<FormatError> {
+        *self.format_error.lock().unwrap()
+    }
+
+    pub fn packet_stats(&self) -> &PacketStats {
+        &self.packet_stats
+    }
+
+    /// The name of the stream currently played.
+    pub fn accepted_stream_name(&self) -> Option<String> {
+        self.accepted_stream_name.lock().unwrap().clone()
+    }
+
+    /// The format of the last stream played.
+    pub fn stream_format(&self) -> Option<StreamFormat> {
+        *self.stream_format.lock().unwrap()
+    }
+
+    /// The sender packets are currently played from.
+    pub fn locked_source(&self) -> Option<SocketAddr> {
+        *self.locked_source.lock().unwrap()
+    }
+
+    fn receive_packet<'a>(
         socket: Arc<UdpSocket>,
-        incoming_addr: &IpAddr,
-        incoming_stream_name: &str,
-        channels: &u8,
-        buf: &mut [u8],
-    ) -> Result<Packet> {
-        let (amt, src) = (*socket).recv_from(buf).context(format!(
-            "Failed to receive packet from socket: {:?}",
-            socket
-        ))?;
+        sources: &Sources,
+        source_port: Option<u16>,
+        stream_name: &mut StreamNameMatcher,
+        channels: Option<u16>,
+        buf: &'a mut [u8],
+    ) -> Result<Option<(PacketRef<'a>, SocketAddr)>> {
+        // Timeouts only let the loop check whether to stop, so they return
+        // without building an error
+        let (amt, src) = match socket.recv_from(buf) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => return Ok(None),
+            Err(e) => return Err(e).context("Failed to receive packet from socket"),
+        };
+        let src = net::canonical_addr(src);
 
-        check_src(&incoming_addr, &src)?;
+        check_src(sources, source_port, &src)?;
 
-        let packet = Packet::try_from(&buf[..amt])?;
+        let packet = PacketRef::try_from(&buf[..amt])?;
 
-        check_audio_pkt(&incoming_stream_name, channels, &packet)?;
+        check_audio_pkt(stream_name, channels, &packet)?;
 
-        Ok(packet)
+        Ok(Some((packet, src)))
     }
 }
 
@@ -189,23 +439,110 @@ impl Drop for VbanReceptorSocket {
     }
 }
 
-fn check_src(ip_address: &IpAddr, src: &SocketAddr) -> Result<()> {
-    if &src.ip() != ip_address {
+/// Binds every interface, dual-stack so IPv4 and IPv6 senders both reach
+/// the socket, unless IPv4 multicast groups are joined or IPv6 is not
+/// available. IPv4 and IPv6 groups cannot be mixed, as only some systems
+/// join IPv4 groups from an IPv6 socket.
+fn bind_any(
+    port: u16,
+    multicast_groups: &[(IpAddr, Option<u32>)],
+    options: &SocketOptions,
+) -> Result<UdpSocket> {
+    let ipv4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let ipv4_groups = multicast_groups.iter().any(|(group, _)| group.is_ipv4());
+    if ipv4_groups && multicast_groups.iter().any(|(group, _)| group.is_ipv6()) {
+        return Err(anyhow!(
+            "IPv4 and IPv6 multicast groups cannot be joined together"
+        ));
+    }
+    if ipv4_groups {
+        return net::bind_udp(ipv4, options);
+    }
+
+    net::bind_udp(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)), options).or_else(|e| {
+        if options.ipv6_only {
+            return Err(e);
+        }
+        log::warn(&format!("{:#}, falling back to IPv4", e));
+        net::bind_udp(ipv4, options)
+    })
+}
+
+/// Joins a multicast group on `interface`, or on the interface of the group
+/// scope for IPv6 link-local groups such as `ff12::1%eth0`.
+fn join_multicast(
+    socket: &UdpSocket,
+    group: IpAddr,
+    scope_id: Option<u32>,
+    interface: Option<Interface>,
+) -> Result<()> {
+    match group {
+        IpAddr::V4(group) if group.is_multicast() => {
+            let interface = match interface {
+                Some(Interface::Addr(addr)) => addr,
+                Some(Interface::Index(_)) => {
+                    return Err(anyhow!(
+                        "IPv4 multicast interfaces are given by address, not by name"
+                    ))
+                }
+                None => Ipv4Addr::UNSPECIFIED,
+            };
+            socket
+                .join_multicast_v4(&group, &interface)
+                .with_context(|| format!("Failed to join multicast group {}", group))
+        }
+        IpAddr::V6(group) if group.is_multicast() => {
+            let interface = match (scope_id, interface) {
+                (Some(index), _) | (None, Some(Interface::Index(index))) => index,
+                (None, Some(Interface::Addr(_))) => {
+                    return Err(anyhow!(
+                        "IPv6 multicast interfaces are given by name or index, not by address"
+                    ))
+                }
+                (None, None) => 0,
+            };
+            socket
+                .join_multicast_v6(&group, interface)
+                .with_context(|| format!("Failed to join multicast group {}", group))
+        }
+        _ => Err(anyhow!("{} is not a multicast group", group)),
+    }
+}
+
+/// Whether the receive call woke up on `RECEIVE_TIMEOUT` without a packet.
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn check_src(sources: &Sources, source_port: Option<u16>, src: &SocketAddr) -> Result<()> {
+    if !sources.contains(&src.ip()) {
         return Err(anyhow!("Wrong source"));
     }
 
+    if source_port.is_some_and(|port| src.port() != port) {
+        return Err(anyhow!("Wrong source port"));
+    }
+
     Ok(())
 }
 
-fn check_audio_pkt(stream_name: &str, channels: &u8, pkt: &Packet) -> Result<()> {
+fn check_audio_pkt(
+    stream_name: &mut StreamNameMatcher,
+    channels: Option<u16>,
+    pkt: &PacketRef,
+) -> Result<()> {
     let header = pkt.header();
 
     // Check stream name
-    if header.stream_name() != stream_name {
-        return Err(anyhow!("Wrong stream name"));
+    if !stream_name.matches(header.stream_name_str()) {
+        stream_name.ignore(header.stream_name_str());
+        return Err(IgnoredStream.into());
     }
 
-    if header.num_channels() != *channels {
+    if channels.is_some_and(|channels| header.num_channels() != channels) {
         return Err(anyhow!("Wrong number of channels"));
     }
 
@@ -214,8 +551,199 @@ fn check_audio_pkt(stream_name: &str, channels: &u8, pkt: &Packet) -> Result<()>
     }
 
     if !matches!(header.codec(), Codec::PCM) {
-        return Err(anyhow!("Wrong codec"));
+        return Err(FormatError::UnsupportedCodec(header.codec()).into());
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    use ringbuf::HeapRb;
+
+    use crate::protocol::packet::Packet;
+
+    struct Sender {
+        socket: UdpSocket,
+        stream_name: &'static str,
+        frame_number: u32,
+    }
+
+    impl Sender {
+        fn new(stream_name: &'static str) -> Self {
+            Self {
+                socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+                stream_name,
+                frame_number: 0,
+            }
+        }
+
+        /// Another stream sent from the same socket.
+        fn with_stream_name(&self, stream_name: &'static str) -> Self {
+            Self {
+                socket: self.socket.try_clone().unwrap(),
+                stream_name,
+                frame_number: 0,
+            }
+        }
+
+        fn addr(&self) -> SocketAddr {
+            self.socket.local_addr().unwrap()
+        }
+
+        fn send(&mut self, receptor: SocketAddr) {
+            let mut header = Header::new(self.stream_name);
+            header.set_num_samples(32);
+            header.set_frame_number(self.frame_number);
+            self.frame_number += 1;
+            let data = vec![0; header.payload_size()];
+            let bytes: Vec<u8> = Packet::new(header, data).into();
+            self.socket.send_to(&bytes, receptor).unwrap();
+        }
+    }
+
+    /// Sends from `senders` in turn every few milliseconds until `done`
+    /// holds or `timeout` runs out, returning whether it held.
+    fn send_until(
+        senders: &mut [&mut Sender],
+        receptor: &VbanReceptorSocket,
+        timeout: Duration,
+        done: impl Fn(&VbanReceptorSocket) -> bool,
+    ) -> bool {
+        let addr = receptor.socket.local_addr().unwrap();
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            for sender in senders.iter_mut() {
+                sender.send(addr);
+            }
+            thread::sleep(Duration::from_millis(5));
+            if done(receptor) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn start_receptor(stream_name: StreamName) -> VbanReceptorSocket {
+        let (producer, _consumer) = HeapRb::<f64>::new(1 << 16).split();
+        let mut receptor = VbanReceptorSocketBuilder::default()
+            .bind_addr(Some(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))))
+            .port(0)
+            .socket_options(SocketOptions::default())
+            .sources(Sources::Any)
+            .stream_name(stream_name)
+            .reorder_window(3)
+            .output_sample_rate(48000)
+            .output_channels(2)
+            .resample_quality(ResampleQuality::default())
+            .channel_map(ChannelMap::default())
+            .producer(producer)
+            .build()
+            .unwrap();
+        receptor.start().unwrap();
+        receptor
+    }
+
+    #[test]
+    fn first_stream_moves_on_when_the_locked_stream_stops() {
+        let mut receptor = start_receptor(StreamName::First);
+        let mut first = Sender::new("First");
+        let mut second = Sender::new("Second");
+
+        assert!(send_until(
+            &mut [&mut first],
+            &receptor,
+            Duration::from_secs(2),
+            |receptor| receptor.accepted_stream_name().as_deref() == Some("First"),
+        ));
+
+        // The second stream is ignored while the first one plays
+        send_until(
+            &mut [&mut first, &mut second],
+            &receptor,
+            Duration::from_millis(300),
+            |_| false,
+        );
+        assert_eq!(receptor.accepted_stream_name().as_deref(), Some("First"));
+        assert_eq!(receptor.locked_source(), Some(first.addr()));
+
+        // And played once the first one stopped, although its packets kept
+        // the socket from timing out
+        assert!(send_until(
+            &mut [&mut second],
+            &receptor,
+            // Past the one second a silent sender stays locked
+            Duration::from_secs(3),
+            |receptor| receptor.accepted_stream_name().as_deref() == Some("Second"),
+        ));
+        assert_eq!(receptor.locked_source(), Some(second.addr()));
+
+        receptor.stop().unwrap();
+    }
+
+    #[test]
+    fn locks_onto_one_of_several_matching_streams() {
+        let cases = [
+            (StreamName::Exact("Mic".to_string()), ["Mic", "MIC "]),
+            (StreamName::Glob("Mic*".to_string()), ["Mic1", "Mic2"]),
+            (
+                StreamName::Regex("^Mic[0-9]$".to_string()),
+                ["Mic1", "Mic2"],
+            ),
+        ];
+
+        for (stream_name, [first_name, second_name]) in cases {
+            let mut receptor = start_receptor(stream_name.clone());
+            let mut first = Sender::new(first_name);
+            let mut second = first.with_stream_name(second_name);
+
+            assert!(send_until(
+                &mut [&mut first],
+                &receptor,
+                Duration::from_secs(2),
+                |receptor| receptor.accepted_stream_name().as_deref() == Some(first_name),
+            ));
+
+            // Packets of the second stream would be duplicates of the first
+            // one had they been mixed in
+            send_until(
+                &mut [&mut first, &mut second],
+                &receptor,
+                Duration::from_millis(300),
+                |_| false,
+            );
+            assert_eq!(
+                receptor.accepted_stream_name().as_deref(),
+                Some(first_name),
+                "{:?}",
+                stream_name
+            );
+            assert_eq!(receptor.packet_stats().duplicates(), 0, "{:?}", stream_name);
+            assert_eq!(receptor.packet_stats().late(), 0, "{:?}", stream_name);
+
+            // The second stream plays once the first one stopped
+            assert!(send_until(
+                &mut [&mut second],
+                &receptor,
+                Duration::from_secs(3),
+                |receptor| receptor.accepted_stream_name().as_deref() == Some(second_name),
+            ));
+
+            receptor.stop().unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_mixed_multicast_families() {
+        let groups = [
+            ("239.0.0.1".parse().unwrap(), None),
+            ("ff15::1".parse().unwrap(), None),
+        ];
+        assert!(bind_any(0, &groups, &SocketOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::Result;
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+
+use crate::utils::log;
+use crate::utils::net;
+use crate::utils::resolve;
+
+/// How long the locked sender may stay silent before another one is
+/// accepted.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Senders the receptor accepts packets from.
+#[derive(Clone, Debug)]
+pub enum Sources {
+    Any,
+    Allow(Vec<Source>),
+}
+
+/// An accepted address range, or a hostname and the addresses it last
+/// resolved to, shared between clones so they all follow address changes.
+#[derive(Clone, Debug)]
+pub enum Source {
+    Net(IpNet),
+    Host(String, Arc<Mutex<Vec<IpAddr>>>),
+}
+
+impl Sources {
+    /// Parses a list of addresses, CIDR ranges such as `10.0.0.0/24` or
+    /// `fd00::/64` and hostnames, resolving the hostnames. The scope of IPv6
+    /// addresses such as `fe80::1%eth0` is ignored.
+    pub fn parse(sources: &[String]) -> Result<Self> {
+        let sources = sources
+            .iter()
+            .map(|source| parse_source(source))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Sources::Allow(sources))
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match self {
+            Sources::Any => true,
+            Sources::Allow(sources) => sources.iter().any(|source| match source {
+                Source::Net(net) => net.contains(ip),
+                Source::Host(_, addrs) => addrs.lock().unwrap().contains(ip),
+            }),
+        }
+    }
+
+    pub fn has_hosts(&self) -> bool {
+        match self {
+            Sources::Any => false,
+            Sources::Allow(sources) => sources
+                .iter()
+                .any(|source| matches!(source, Source::Host(..))),
+        }
+    }
+
+    /// Resolves the hostnames again, keeping their previous addresses when
+    /// the lookup fails.
+    pub fn refresh(&self) {
+        let Sources::Allow(sources) = self else {
+            return;
+        };
+
+        for source in sources {
+            let Source::Host(host, addrs) = source else {
+                continue;
+            };
+            match resolve_ips(host) {
+                Ok(ips) => {
+                    let mut addrs = addrs.lock().unwrap();
+                    if ips.len() != addrs.len() || ips.iter().any(|ip| !addrs.contains(ip)) {
+                        log::info(&format!("{} now resolves to {:?}", host, ips));
+                        *addrs = ips;
+                    }
+                }
+                Err(e) => log::warn(&format!("{:#}, keeping its previous addresses", e)),
+            }
+        }
+    }
+}
+
+fn parse_source(source: &str) -> Result<Source> {
+    if let Ok(net) = source.parse::<IpNet>() {
+        return Ok(Source::Net(net));
+    }
+
+    match net::parse_scoped_ip(source) {
+        Ok((ip, _)) => Ok(Source::Net(IpNet::from(ip))),
+        Err(_) if resolve::is_hostname(source) => Ok(Source::Host(
+            source.to_string(),
+            Arc::new(Mutex::new(resolve_ips(source)?)),
+        )),
+        Err(e) => Err(e.context(format!("Invalid source address {}", source))),
+    }
+}
+
+fn resolve_ips(host: &str) -> Result<Vec<IpAddr>> {
+    Ok(resolve::resolve(host, 0)?
+        .iter()
+        .map(|addr| addr.ip())
+        .collect())
+}
+
+/// Keeps the receptor on a single sender among the accepted sources, moving
+/// to another one only after the current sender went silent.
+pub struct SourceLock {
+    locked: Arc<Mutex<Option<SocketAddr>>>,
+    current: Option<SocketAddr>,
+    last_seen: Instant,
+}
+
+impl SourceLock {
+    pub fn new(locked: Arc<Mutex<Option<SocketAddr>>>) -> Self {
+        Self {
+            locked,
+            current: None,
+            last_seen: Instant::now(),
+        }
+    }
+
+    /// The sender currently locked on.
+    pub fn current(&self) -> Option<SocketAddr> {
+        self.current
+    }
+
+    /// Whether a valid packet from `src` should be played, locking on `src`
+    /// when no sender is locked. A silent sender is only released by
+    /// `expire`.
+    pub fn accept(&mut self, src: SocketAddr) -> bool {
+        match self.current {
+            Some(current) if current != src => return false,
+            Some(_) => {}
+            None => {
+                log::info(&format!("Receiving from {}", src));
+                self.current = Some(src);
+                *self.locked.lock().unwrap() = Some(src);
+            }
+        }
+        self.last_seen = Instant::now();
+
+        true
+    }
+
+    /// Releases the locked sender once no packet of it was played for
+    /// `LOCK_TIMEOUT`, returning true when it did.
+    pub fn expire(&mut self) -> bool {
+        if self.current.is_some() && self.last_seen.elapsed() >= LOCK_TIMEOUT {
+            self.current = None;
+            *self.locked.lock().unwrap() = None;
+            return true;
+        }
+
+        false
+    }
+}
@@ -16,8 +16,16 @@ use ringbuf::{Consumer, HeapRb, Producer, SharedRb};
 use crate::utils::cpal::{Device, Host, Status, StreamStatus};
 use crate::utils::{self, log};
 
-pub type VbanStreamConsumer = Consumer<i16, Arc<SharedRb<i16, Vec<MaybeUninit<i16>>>>>;
-pub type VbanStreamProducer = Producer<i16, Arc<SharedRb<i16, Vec<MaybeUninit<i16>>>>>;
+use super::concealment::{Concealer, Concealment};
+use super::jitter::{JitterBuffer, JitterStats};
+use super::socket::StreamFormat;
+
+/// Marks audio lost on the network in the ring buffer, so the output stage
+/// can tell it apart from received silence.
+pub const MISSING_SAMPLE: f64 = f64::NAN;
+
+pub type VbanStreamConsumer = Consumer<f64, Arc<SharedRb<f64, Vec<MaybeUninit<f64>>>>>;
+pub type VbanStreamProducer = Producer<f64, Arc<SharedRb<f64, Vec<MaybeUninit<f64>>>>>;
 
 pub struct StreamWrapper(Arc<Mutex<cpal::Stream>>);
 
@@ -25,41 +33,32 @@ unsafe impl Send for StreamWrapper {}
 unsafe impl Sync for StreamWrapper {}
 
 fn start_ring_buffer(
-    latency: f32,
+    latency_frames: usize,
     config: &SupportedStreamConfig,
 ) -> (VbanStreamProducer, VbanStreamConsumer) {
-    let latency_frames = (latency / 1_000.0) * config.sample_rate().0 as f32;
-    let latency_samples = latency_frames as usize * config.channels() as usize;
+    let latency_samples = latency_frames * config.channels() as usize;
 
-    let ring = HeapRb::<i16>::new(latency_samples * 2);
+    let ring = HeapRb::<f64>::new(latency_samples * 2);
     let (mut producer, consumer) = ring.split();
 
     // Fill the samples with 0.0 equal to the length of the delay.
     for _ in 0..latency_samples {
         // The ring buffer has twice as much space as necessary to add latency here,
         // so this should never fail
-        producer.push(0.0.to_sample::<i16>()).ok();
+        producer.push(0.0).ok();
     }
 
     (producer, consumer)
 }
 
+#[derive(Default)]
 pub struct VbanReceptorStreamBuilder {
     device_name: Option<String>,
     device_type: Option<String>,
     host_name: Option<String>,
     latency: Option<f32>,
-}
-
-impl Default for VbanReceptorStreamBuilder {
-    fn default() -> Self {
-        Self {
-            device_name: None,
-            device_type: None,
-            host_name: None,
-            latency: None,
-        }
-    }
+    concealment: Option<Concealment>,
+    format: Option<StreamFormat>,
 }
 
 impl VbanReceptorStreamBuilder {
@@ -83,11 +82,24 @@ impl VbanReceptorStreamBuilder {
         self
     }
 
+    pub fn concealment(mut self, concealment: Concealment) -> Self {
+        self.concealment = Some(concealment);
+        self
+    }
+
+    /// Opens the device with the channels and sample rate of this format
+    /// when it supports them, instead of its default config.
+    pub fn format(mut self, format: Option<StreamFormat>) -> Self {
+        self.format = format;
+        self
+    }
+
     pub fn build(self) -> Result<(VbanReceptorStream, VbanStreamProducer)> {
         let device_name = self.device_name.context("device name is required")?;
         let device_type = self.device_type.context("device type is required")?;
         let host_name = self.host_name.context("host name is required")?;
         let latency = self.latency.context("latency is required")?;
+        let concealment = self.concealment.context("concealment is required")?;
 
         let host = Arc::new(utils::cpal::host_by_name(&host_name)?);
         let device = Arc::new(match device_type.as_str() {
@@ -99,15 +111,37 @@ impl VbanReceptorStreamBuilder {
                 .context("no input device available")?,
             _ => return Err(anyhow!("invalid device type")),
         });
-        let device_config = device.default_output_config()?;
+        let device_config = match self.format {
+            Some(format) => {
+                device.output_config_matching(format.channels, format.sample_rate.hz())?
+            }
+            None => device.default_output_config()?,
+        };
         let sample_format = device_config.sample_format();
-        let (producer, consumer) = start_ring_buffer(latency, &device_config);
+        let latency_frames = ((latency / 1_000.0) * device_config.sample_rate().0 as f32) as usize;
+        let (producer, consumer) = start_ring_buffer(latency_frames, &device_config);
+        let jitter_stats = Arc::new(JitterStats::default());
+        let jitter_buffer = JitterBuffer::new(
+            consumer,
+            device_config.channels() as usize,
+            device_config.sample_rate().0,
+            latency_frames,
+            jitter_stats.clone(),
+        );
+        let concealer = Concealer::new(
+            concealment,
+            device_config.channels() as usize,
+            device_config.sample_rate().0,
+        );
         let status = Arc::new(Mutex::new(Status::Ok));
+        #[allow(clippy::arc_with_non_send_sync)]
         let stream = StreamWrapper(Arc::new(Mutex::new(build_stream_for_sample_format(
             sample_format,
             StreamParams {
                 device: device.clone(),
-                consumer,
+                config: device_config.clone(),
+                jitter_buffer,
+                concealer,
                 status: status.clone(),
             },
         )?)));
@@ -118,6 +152,9 @@ impl VbanReceptorStreamBuilder {
                 device,
                 stream,
                 status,
+                jitter_stats,
+                sample_rate: device_config.sample_rate().0,
+                channels: device_config.channels() as usize,
             },
             producer,
         ))
@@ -129,6 +166,9 @@ pub struct VbanReceptorStream {
     device: Arc<cpal::Device>,
     stream: StreamWrapper,
     status: StreamStatus,
+    jitter_stats: Arc<JitterStats>,
+    sample_rate: u32,
+    channels: usize,
 }
 
 impl VbanReceptorStream {
@@ -156,6 +196,20 @@ impl VbanReceptorStream {
         true
     }
 
+    /// Sample rate the output device was opened at.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of channels the output device was opened with.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn jitter_stats(&self) -> &JitterStats {
+        &self.jitter_stats
+    }
+
     fn running(&self) -> bool {
         match *self.status.lock().unwrap() {
             Status::Ok => true,
@@ -166,7 +220,9 @@ impl VbanReceptorStream {
 
 struct StreamParams {
     device: Arc<cpal::Device>,
-    consumer: VbanStreamConsumer,
+    config: SupportedStreamConfig,
+    jitter_buffer: JitterBuffer,
+    concealer: Concealer,
     status: StreamStatus,
 }
 
@@ -191,19 +247,20 @@ fn build_stream_for_sample_format(
 
 fn build_stream<T>(params: StreamParams) -> Result<cpal::Stream>
 where
-    T: SizedSample + FromSample<i16> + FromSample<f32> + Send + Sync,
+    T: SizedSample + FromSample<f64> + Send + Sync,
 {
     let StreamParams {
         device,
-        consumer,
+        config,
+        jitter_buffer,
+        concealer,
         status,
     } = params;
-    let config = device.default_output_config()?;
     let channels = config.channels() as usize;
 
     let stream = device.build_output_stream(
         &config.into(),
-        build_data_callback::<T>(consumer, channels),
+        build_data_callback::<T>(jitter_buffer, concealer, channels),
         move |err| {
             log::error(&format!("an error occurred on stream: {}", err));
             *status.lock().unwrap() = Status::Err(err);
@@ -215,19 +272,22 @@ where
 }
 
 fn build_data_callback<T>(
-    mut consumer: VbanStreamConsumer,
+    mut jitter_buffer: JitterBuffer,
+    mut concealer: Concealer,
     channels: usize,
 ) -> impl FnMut(&mut [T], &cpal::OutputCallbackInfo) + Send + 'static
 where
-    T: SizedSample + FromSample<i16> + FromSample<f32> + Send + Sync,
+    T: SizedSample + FromSample<f64> + Send + Sync,
 {
+    let mut samples = vec![0.0; channels];
+
     move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+        jitter_buffer.update(data.len() / channels);
         for frame in data.chunks_mut(channels) {
-            for sample in frame {
-                *sample = match consumer.pop() {
-                    Some(s) => s.to_sample::<T>(),
-                    None => 0.0.to_sample::<T>(),
-                };
+            let received = jitter_buffer.next_frame(&mut samples);
+            concealer.process(&mut samples, received);
+            for (sample, value) in frame.iter_mut().zip(&samples) {
+                *sample = value.to_sample::<T>();
             }
         }
     }
@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+
+use std::sync::{Arc, Mutex};
+
+use regex::Regex;
+
+use crate::utils::log;
+
+/// Which incoming streams the receptor plays, by name.
+///
+/// Exact names and globs ignore ASCII case and trailing spaces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamName {
+    Exact(String),
+    /// A pattern where `*` matches any run of characters and `?` any single
+    /// character.
+    Glob(String),
+    Regex(String),
+    /// Lock onto the first audio stream received.
+    First,
+}
+
+enum Pattern {
+    Exact(String),
+    Glob(String),
+    Regex(Regex),
+    First,
+}
+
+/// Matches incoming stream names against a `StreamName`, locking onto the
+/// first matching name so several matching streams are never mixed.
+pub struct StreamNameMatcher {
+    pattern: Pattern,
+    locked: Option<String>,
+    ignored: Option<String>,
+    accepted: Arc<Mutex<Option<String>>>,
+}
+
+impl StreamNameMatcher {
+    pub fn new(stream_name: &StreamName, accepted: Arc<Mutex<Option<String>>>) -> Result<Self> {
+        let pattern = match stream_name {
+            StreamName::Exact(name) => Pattern::Exact(name.trim_end().to_string()),
+            StreamName::Glob(glob) => Pattern::Glob(glob.trim_end().to_string()),
+            StreamName::Regex(regex) => Pattern::Regex(
+                Regex::new(regex)
+                    .with_context(|| format!("Invalid stream name regex {}", regex))?,
+            ),
+            StreamName::First => Pattern::First,
+        };
+
+        Ok(Self {
+            pattern,
+            locked: None,
+            ignored: None,
+            accepted,
+        })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let matches = match &self.pattern {
+            Pattern::Exact(pattern) => name.trim_end().eq_ignore_ascii_case(pattern),
+            Pattern::Glob(pattern) => glob_match(pattern.as_bytes(), name.trim_end().as_bytes()),
+            Pattern::Regex(regex) => regex.is_match(name),
+            Pattern::First => true,
+        };
+
+        matches && self.locked.as_deref().is_none_or(|locked| locked == name)
+    }
+
+    /// Records `name` as the stream being played, locking onto it until
+    /// released.
+    pub fn accept(&mut self, name: &str) {
+        if self.locked.is_none() {
+            self.locked = Some(name.to_string());
+        }
+
+        let mut accepted = self.accepted.lock().unwrap();
+        if accepted.as_deref() != Some(name) {
+            *accepted = Some(name.to_string());
+        }
+    }
+
+    /// Logs a stream whose name does not match, once until another stream
+    /// is ignored.
+    pub fn ignore(&mut self, name: &str) {
+        if self.ignored.as_deref() != Some(name) {
+            log::warn(&format!(
+                "Ignoring stream \"{}\", its name does not match",
+                name
+            ));
+            self.ignored = Some(name.to_string());
+        }
+    }
+
+    /// Forgets the stream being played, letting the matcher lock onto a new
+    /// one.
+    pub fn release(&mut self) {
+        self.locked = None;
+        self.ignored = None;
+        *self.accepted.lock().unwrap() = None;
+    }
+}
+
+/// Matches `name` against a glob of `*` and `?` wildcards, backtracking to
+/// the last `*` on a mismatch.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == b'?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
@@ -57,6 +57,14 @@ impl Host for cpal::Host {
 pub trait Device {
     fn default_config(&self) -> Result<cpal::SupportedStreamConfig>;
 
+    fn input_config_with_channels(&self, channels: u16) -> Result<cpal::SupportedStreamConfig>;
+
+    fn output_config_matching(
+        &self,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Result<cpal::SupportedStreamConfig>;
+
     fn is_output(&self) -> bool;
 
     fn is_input(&self) -> bool;
@@ -80,30 +88,77 @@ impl Device for cpal::Device {
         Err(anyhow!("No default config found"))
     }
 
-    fn is_output(&self) -> bool {
-        if let Ok(_) = self.default_output_config() {
-            return true;
+    /// Returns an input config with the requested channel count, keeping the
+    /// default sample rate and format. Falls back to the default input config
+    /// when the device has no such config, leaving the caller to remap.
+    fn input_config_with_channels(&self, channels: u16) -> Result<cpal::SupportedStreamConfig> {
+        let default_config = self.default_input_config()?;
+        if default_config.channels() == channels {
+            return Ok(default_config);
         }
-        false
+
+        let config = self.supported_input_configs()?.find(|config| {
+            config.channels() == channels
+                && config.sample_format() == default_config.sample_format()
+                && config.min_sample_rate() <= default_config.sample_rate()
+                && config.max_sample_rate() >= default_config.sample_rate()
+        });
+
+        Ok(match config {
+            Some(config) => config.with_sample_rate(default_config.sample_rate()),
+            None => default_config,
+        })
     }
 
-    fn is_input(&self) -> bool {
-        if let Ok(_) = self.default_input_config() {
-            return true;
+    /// Returns an output config with the requested channel count and sample
+    /// rate, preferring the default sample format. Falls back to the default
+    /// output config when the device has no such config, leaving the caller
+    /// to convert.
+    fn output_config_matching(
+        &self,
+        channels: u16,
+        sample_rate: u32,
+    ) -> Result<cpal::SupportedStreamConfig> {
+        let default_config = self.default_output_config()?;
+        let sample_rate = cpal::SampleRate(sample_rate);
+        if default_config.channels() == channels && default_config.sample_rate() == sample_rate {
+            return Ok(default_config);
         }
-        false
+
+        let mut configs = self
+            .supported_output_configs()?
+            .filter(|config| {
+                config.channels() == channels
+                    && config.min_sample_rate() <= sample_rate
+                    && config.max_sample_rate() >= sample_rate
+            })
+            .collect::<Vec<_>>();
+        configs.sort_by_key(|config| config.sample_format() != default_config.sample_format());
+
+        Ok(match configs.into_iter().next() {
+            Some(config) => config.with_sample_rate(sample_rate),
+            None => default_config,
+        })
+    }
+
+    fn is_output(&self) -> bool {
+        self.default_output_config().is_ok()
+    }
+
+    fn is_input(&self) -> bool {
+        self.default_input_config().is_ok()
     }
 
     fn is_default_output(&self, host: &cpal::Host) -> bool {
         if let Some(default_output) = host.default_output_device() {
-            return default_output.compare_name(&self);
+            return default_output.compare_name(self);
         }
         false
     }
 
     fn is_default_input(&self, host: &cpal::Host) -> bool {
         if let Some(default_input) = host.default_input_device() {
-            return default_input.compare_name(&self);
+            return default_input.compare_name(self);
         }
         false
     }
@@ -1,3 +1,6 @@
 pub mod cpal;
 pub mod errors;
 pub mod log;
+pub mod net;
+pub mod resample;
+pub mod resolve;
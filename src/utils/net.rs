@@ -0,0 +1,333 @@
+use anyhow::{anyhow, Context, Result};
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Options applied to a UDP socket before it is bound.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Sets `SO_REUSEADDR`, allowing another socket to bind the same address.
+    pub reuse_address: bool,
+    /// Sets `SO_REUSEPORT`, allowing several processes to bind the same port.
+    /// Only available on Unix.
+    pub reuse_port: bool,
+    /// Size of the kernel send buffer, in bytes. Defaults to the system one.
+    pub send_buffer_size: Option<usize>,
+    /// Size of the kernel receive buffer, in bytes. Defaults to the system
+    /// one.
+    pub receive_buffer_size: Option<usize>,
+    /// Type of service byte of outgoing packets, holding the DSCP in its
+    /// upper six bits.
+    pub tos: Option<u32>,
+    /// Keeps IPv6 sockets from also handling IPv4 traffic. IPv6 sockets are
+    /// dual-stack otherwise, whatever the system default.
+    pub ipv6_only: bool,
+}
+
+/// A local network interface, by IPv4 address or by name or index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interface {
+    Addr(Ipv4Addr),
+    Index(u32),
+}
+
+impl Interface {
+    /// Parses an IPv4 address such as `192.168.0.10`, an interface name such
+    /// as `eth0` or an interface index.
+    pub fn parse(interface: &str) -> Result<Self> {
+        match interface.parse::<Ipv4Addr>() {
+            Ok(addr) => Ok(Interface::Addr(addr)),
+            Err(_) => Ok(Interface::Index(interface_index(interface)?)),
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Marks outgoing packets with a DSCP class from 0 to 63, such as 46 for
+    /// expedited forwarding.
+    pub fn set_dscp(&mut self, dscp: u8) {
+        self.tos = Some(u32::from(dscp) << 2);
+    }
+}
+
+/// Creates a UDP socket with `options` and binds it to `addr`.
+pub fn bind_udp(addr: SocketAddr, options: &SocketOptions) -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if options.reuse_address {
+        socket.set_reuse_address(true)?;
+    }
+    if options.reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        socket
+            .set_send_buffer_size(size)
+            .context("Failed to set the send buffer size")?;
+    }
+    if let Some(size) = options.receive_buffer_size {
+        socket
+            .set_recv_buffer_size(size)
+            .context("Failed to set the receive buffer size")?;
+    }
+    if let Some(tos) = options.tos {
+        if tos > 0xff {
+            return Err(anyhow!("DSCP must be between 0 and 63"));
+        }
+        if addr.is_ipv4() {
+            socket.set_tos(tos).context("Failed to set the TOS")?;
+        } else {
+            set_traffic_class(&socket, tos).context("Failed to set the traffic class")?;
+            if !options.ipv6_only {
+                // Marks IPv4 packets of a dual-stack socket, on the systems
+                // applying IPv4 options to IPv6 sockets
+                let _ = socket.set_tos(tos);
+            }
+        }
+    }
+    if addr.is_ipv6() {
+        socket.set_only_v6(options.ipv6_only)?;
+    }
+
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind {}", addr))?;
+
+    Ok(socket.into())
+}
+
+/// Parses an address given as `ip`, `ip:port`, `[ipv6]:port` or with an
+/// IPv6 scope such as `fe80::1%eth0` or `[fe80::1%2]:6980`, using
+/// `default_port` when the port is missing.
+pub fn parse_socket_addr(addr: &str, default_port: u16) -> Result<SocketAddr> {
+    let (host, port) = split_host_port(addr)?;
+    let (ip, scope_id) = parse_scoped_ip(host)?;
+
+    Ok(scoped_socket_addr(
+        ip,
+        scope_id,
+        port.unwrap_or(default_port),
+    ))
+}
+
+/// Splits the port off an address. IPv6 addresses only take a port when
+/// written in brackets.
+pub fn split_host_port(addr: &str) -> Result<(&str, Option<u16>)> {
+    let (host, port) = match addr.strip_prefix('[') {
+        Some(rest) => {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or(anyhow!("Missing ] in {}", addr))?;
+            if rest.is_empty() {
+                (host, None)
+            } else {
+                let port = rest
+                    .strip_prefix(':')
+                    .ok_or(anyhow!("Invalid address {}", addr))?;
+                (host, Some(port))
+            }
+        }
+        None => match addr.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host, Some(port)),
+            _ => (addr, None),
+        },
+    };
+    let port = port
+        .map(|port| port.parse::<u16>())
+        .transpose()
+        .with_context(|| format!("Invalid port in {}", addr))?;
+
+    Ok((host, port))
+}
+
+/// Parses an IP address with an optional IPv6 scope, given as an interface
+/// name or index after a `%`.
+pub fn parse_scoped_ip(ip: &str) -> Result<(IpAddr, Option<u32>)> {
+    match ip.split_once('%') {
+        Some((ip, scope)) => {
+            let ip = ip
+                .parse::<Ipv6Addr>()
+                .with_context(|| format!("Invalid IPv6 address {}", ip))?;
+            Ok((IpAddr::V6(ip), Some(interface_index(scope)?)))
+        }
+        None => {
+            let ip = ip
+                .parse::<IpAddr>()
+                .with_context(|| format!("Invalid IP address {}", ip))?;
+            Ok((ip, None))
+        }
+    }
+}
+
+pub fn scoped_socket_addr(ip: IpAddr, scope_id: Option<u32>, port: u16) -> SocketAddr {
+    match ip {
+        IpAddr::V4(ip) => SocketAddr::from((ip, port)),
+        IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id.unwrap_or(0))),
+    }
+}
+
+/// Turns IPv4-mapped IPv6 addresses, as received on a dual-stack socket,
+/// back into IPv4 ones.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::from((ip, v6.port())),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Maps IPv4 addresses to IPv6 ones for sending from an IPv6 socket.
+pub fn socket_family_addr(addr: SocketAddr, ipv6: bool) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) if ipv6 => SocketAddr::from((v4.ip().to_ipv6_mapped(), v4.port())),
+        _ => addr,
+    }
+}
+
+/// Looks up the index of a network interface given by name or index.
+pub fn interface_index(interface: &str) -> Result<u32> {
+    if let Ok(index) = interface.parse::<u32>() {
+        return Ok(index);
+    }
+
+    interface_index_by_name(interface)
+}
+
+#[cfg(unix)]
+fn interface_index_by_name(name: &str) -> Result<u32> {
+    let c_name =
+        std::ffi::CString::new(name).with_context(|| format!("Invalid interface name {}", name))?;
+    // SAFETY: `c_name` is a valid NUL terminated string
+    let index = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+    if index == 0 {
+        return Err(anyhow!("Unknown interface {}", name));
+    }
+
+    Ok(index)
+}
+
+#[cfg(not(unix))]
+fn interface_index_by_name(name: &str) -> Result<u32> {
+    Err(anyhow!(
+        "Unknown interface {}, interfaces are given by index on this platform",
+        name
+    ))
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn set_traffic_class(socket: &Socket, tclass: u32) -> Result<()> {
+    Ok(socket.set_tclass_v6(tclass)?)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn set_traffic_class(_socket: &Socket, _tclass: u32) -> Result<()> {
+    Err(anyhow!(
+        "IPv6 traffic class is not supported on this platform"
+    ))
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> Result<()> {
+    Ok(socket.set_reuse_port(true)?)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> Result<()> {
+    Err(anyhow!("SO_REUSEPORT is not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_host_and_port() {
+        let cases = [
+            ("192.168.0.1", Some(("192.168.0.1", None))),
+            ("192.168.0.1:6980", Some(("192.168.0.1", Some(6980)))),
+            ("studio.local:6980", Some(("studio.local", Some(6980)))),
+            ("::1", Some(("::1", None))),
+            ("fe80::1%eth0", Some(("fe80::1%eth0", None))),
+            ("[::1]", Some(("::1", None))),
+            ("[::1]:6980", Some(("::1", Some(6980)))),
+            ("[fe80::1%2]:6980", Some(("fe80::1%2", Some(6980)))),
+            ("[::1", None),
+            ("[::1]6980", None),
+            ("192.168.0.1:port", None),
+            ("192.168.0.1:65536", None),
+        ];
+        for (addr, expected) in cases {
+            assert_eq!(split_host_port(addr).ok(), expected, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn parses_scoped_ips() {
+        let cases = [
+            ("192.168.0.1", Some(("192.168.0.1", None))),
+            ("::1", Some(("::1", None))),
+            ("fe80::1%2", Some(("fe80::1", Some(2)))),
+            ("192.168.0.1%2", None),
+            ("fe80::1%", None),
+            ("studio.local", None),
+        ];
+        for (ip, expected) in cases {
+            let expected = expected.map(|(ip, scope_id)| (ip.parse::<IpAddr>().unwrap(), scope_id));
+            assert_eq!(parse_scoped_ip(ip).ok(), expected, "{}", ip);
+        }
+    }
+
+    #[test]
+    fn parses_socket_addrs() {
+        let cases = [
+            ("192.168.0.1", "192.168.0.1:6980"),
+            ("192.168.0.1:7000", "192.168.0.1:7000"),
+            ("::1", "[::1]:6980"),
+            ("[::1]:7000", "[::1]:7000"),
+            ("fe80::1%2", "[fe80::1%2]:6980"),
+        ];
+        for (addr, expected) in cases {
+            assert_eq!(
+                parse_socket_addr(addr, 6980).unwrap(),
+                expected.parse::<SocketAddr>().unwrap(),
+                "{}",
+                addr
+            );
+        }
+    }
+
+    #[test]
+    fn maps_between_families() {
+        // Address, as received on or sent from a dual-stack socket
+        let cases = [
+            ("192.168.0.1:6980", "[::ffff:192.168.0.1]:6980"),
+            ("[::1]:6980", "[::1]:6980"),
+            ("[fe80::1%2]:6980", "[fe80::1%2]:6980"),
+        ];
+        for (addr, dual_stack) in cases {
+            let addr = addr.parse::<SocketAddr>().unwrap();
+            let dual_stack = dual_stack.parse::<SocketAddr>().unwrap();
+            assert_eq!(socket_family_addr(addr, true), dual_stack);
+            assert_eq!(socket_family_addr(addr, false), addr);
+            assert_eq!(canonical_addr(dual_stack), addr);
+            assert_eq!(canonical_addr(addr), addr);
+        }
+    }
+}
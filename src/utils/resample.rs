@@ -0,0 +1,309 @@
+use std::f64::consts::PI;
+
+/// Half the number of taps of the `High` quality sinc kernel.
+const SINC_HALF_TAPS: usize = 8;
+/// Fraction of the Nyquist frequency kept by the sinc low pass filter.
+const SINC_CUTOFF: f64 = 0.95;
+
+/// Interpolation used to convert audio between sample rates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Linear interpolation, the cheapest and with the most aliasing.
+    Low,
+    /// Cubic Hermite interpolation.
+    #[default]
+    Medium,
+    /// Windowed sinc interpolation with anti-aliasing when downsampling.
+    High,
+}
+
+impl ResampleQuality {
+    /// Number of input frames on each side of the interpolated position the
+    /// kernel reads.
+    fn half_width(&self) -> usize {
+        match self {
+            ResampleQuality::Low => 1,
+            ResampleQuality::Medium => 2,
+            ResampleQuality::High => SINC_HALF_TAPS,
+        }
+    }
+}
+
+/// Streaming sample rate converter for interleaved frames. Audio already at
+/// the output rate passes through untouched.
+pub struct Resampler {
+    quality: ResampleQuality,
+    input_rate: u32,
+    output_rate: u32,
+    channels: usize,
+    // Input frames not fully consumed yet, starting with the kernel history
+    input: Vec<f64>,
+    // Position of the next output frame, `index` frames plus `phase` output
+    // periods into `input`, kept exact so it does not depend on how the
+    // input is split across calls
+    index: usize,
+    phase: u32,
+    weights: Vec<f64>,
+    frame: Vec<f64>,
+}
+
+impl Resampler {
+    pub fn new(
+        quality: ResampleQuality,
+        input_rate: u32,
+        output_rate: u32,
+        channels: usize,
+    ) -> Self {
+        let half_width = quality.half_width();
+        // Start with silent history so the first frames have neighbours
+        let history = if input_rate == output_rate {
+            0
+        } else {
+            half_width - 1
+        };
+
+        Self {
+            quality,
+            input_rate,
+            output_rate,
+            channels,
+            input: vec![0.0; history * channels],
+            index: history,
+            phase: 0,
+            weights: vec![0.0; 2 * half_width],
+            frame: vec![0.0; channels],
+        }
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Appends interleaved input samples and calls `output` with every
+    /// output frame they complete.
+    pub fn process<I, F>(&mut self, samples: I, mut output: F)
+    where
+        I: IntoIterator<Item = f64>,
+        F: FnMut(&[f64]),
+    {
+        if self.input_rate == self.output_rate {
+            for sample in samples {
+                self.input.push(sample);
+                if self.input.len() == self.channels {
+                    output(&self.input);
+                    self.input.clear();
+                }
+            }
+            return;
+        }
+
+        self.input.extend(samples);
+
+        let channels = self.channels;
+        let half_width = self.quality.half_width();
+        let step = self.input_rate as f64 / self.output_rate as f64;
+        let step_frames = (self.input_rate / self.output_rate) as usize;
+        let step_phase = self.input_rate % self.output_rate;
+        let input_frames = self.input.len() / channels;
+
+        while self.index + half_width < input_frames {
+            let fraction = self.phase as f64 / self.output_rate as f64;
+            self.compute_weights(fraction, step);
+
+            // First input frame read by the kernel
+            let first = (self.index + 1 - half_width) * channels;
+            for (channel, sample) in self.frame.iter_mut().enumerate() {
+                *sample = self
+                    .weights
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, weight)| self.input[first + tap * channels + channel] * weight)
+                    .sum();
+            }
+            output(&self.frame);
+
+            self.index += step_frames;
+            self.phase += step_phase;
+            if self.phase >= self.output_rate {
+                self.phase -= self.output_rate;
+                self.index += 1;
+            }
+        }
+
+        let consumed = (self.index + 1).saturating_sub(half_width);
+        self.input.drain(..consumed * channels);
+        self.index -= consumed;
+    }
+
+    /// Computes the kernel weights of the input frames around a position
+    /// `fraction` of a frame past the frame at `half_width - 1`.
+    fn compute_weights(&mut self, fraction: f64, step: f64) {
+        match self.quality {
+            ResampleQuality::Low => {
+                self.weights[0] = 1.0 - fraction;
+                self.weights[1] = fraction;
+            }
+            ResampleQuality::Medium => {
+                // Catmull-Rom spline
+                let t = fraction;
+                let t2 = t * t;
+                let t3 = t2 * t;
+                self.weights[0] = -0.5 * t3 + t2 - 0.5 * t;
+                self.weights[1] = 1.5 * t3 - 2.5 * t2 + 1.0;
+                self.weights[2] = -1.5 * t3 + 2.0 * t2 + 0.5 * t;
+                self.weights[3] = 0.5 * t3 - 0.5 * t2;
+            }
+            ResampleQuality::High => {
+                // Lower the cutoff below the output Nyquist frequency when
+                // downsampling
+                let cutoff = SINC_CUTOFF * (1.0 / step).min(1.0);
+                let half_width = SINC_HALF_TAPS as f64;
+                let mut sum = 0.0;
+                for (tap, weight) in self.weights.iter_mut().enumerate() {
+                    let x = tap as f64 - (half_width - 1.0) - fraction;
+                    let window = 0.5 * (1.0 + (PI * x / half_width).cos());
+                    *weight = cutoff * sinc(cutoff * x) * window;
+                    sum += *weight;
+                }
+                for weight in self.weights.iter_mut() {
+                    *weight /= sum;
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [ResampleQuality; 3] = [
+        ResampleQuality::Low,
+        ResampleQuality::Medium,
+        ResampleQuality::High,
+    ];
+
+    const RATES: [(u32, u32); 5] = [
+        (48_000, 48_000),
+        (44_100, 48_000),
+        (48_000, 44_100),
+        (96_000, 48_000),
+        (8_000, 48_000),
+    ];
+
+    fn resample(resampler: &mut Resampler, samples: &[f64]) -> Vec<f64> {
+        let mut output = Vec::new();
+        resampler.process(samples.iter().copied(), |frame| {
+            output.extend_from_slice(frame)
+        });
+        output
+    }
+
+    /// Stereo test signal with distinct channels.
+    fn signal(frames: usize) -> Vec<f64> {
+        (0..frames)
+            .flat_map(|frame| {
+                let t = frame as f64 / 100.0;
+                [(2.0 * PI * t).sin(), 0.5 * (2.0 * PI * 3.0 * t).cos()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn output_length_follows_the_rate_ratio() {
+        for quality in QUALITIES {
+            for (input_rate, output_rate) in RATES {
+                let mut resampler = Resampler::new(quality, input_rate, output_rate, 2);
+                let ratio = output_rate as f64 / input_rate as f64;
+                let input_frames = input_rate as usize / 10;
+                let mut output_frames = 0;
+                for chunk in signal(input_frames).chunks(2 * 37) {
+                    output_frames += resample(&mut resampler, chunk).len() / 2;
+                }
+
+                // Frames within the kernel reach of the end are still pending
+                let expected = input_frames as f64 * ratio;
+                let pending = (quality.half_width() + 1) as f64 * ratio;
+                assert!(
+                    (output_frames as f64) <= expected + 1.0
+                        && (output_frames as f64) >= expected - pending - 1.0,
+                    "{:?} {} -> {}: {} frames, expected {}",
+                    quality,
+                    input_rate,
+                    output_rate,
+                    output_frames,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_dc_gain() {
+        for quality in QUALITIES {
+            for (input_rate, output_rate) in RATES {
+                let mut resampler = Resampler::new(quality, input_rate, output_rate, 2);
+                let output = resample(&mut resampler, &[0.5; 2 * 4_800]);
+
+                // Skip the frames reading the silent history
+                let settled = (2.0 * quality.half_width() as f64 * output_rate as f64
+                    / input_rate as f64)
+                    .ceil() as usize;
+                for sample in &output[2 * settled..] {
+                    assert!(
+                        (sample - 0.5).abs() < 1e-9,
+                        "{:?} {} -> {}: {}",
+                        quality,
+                        input_rate,
+                        output_rate,
+                        sample
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn is_continuous_across_calls() {
+        let input = signal(64);
+        for quality in QUALITIES {
+            for (input_rate, output_rate) in RATES {
+                let expected = resample(
+                    &mut Resampler::new(quality, input_rate, output_rate, 2),
+                    &input,
+                );
+
+                // Split anywhere, including within a frame
+                for split in 0..=input.len() {
+                    let mut resampler = Resampler::new(quality, input_rate, output_rate, 2);
+                    let mut output = resample(&mut resampler, &input[..split]);
+                    output.extend(resample(&mut resampler, &input[split..]));
+                    assert!(
+                        output == expected,
+                        "{:?} {} -> {} split at {}",
+                        quality,
+                        input_rate,
+                        output_rate,
+                        split
+                    );
+                }
+            }
+        }
+    }
+}
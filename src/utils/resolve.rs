@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Context, Result};
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::net;
+
+/// How often hostnames are resolved again, so a host whose address changes
+/// is followed without rebuilding the stream.
+pub const RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the resolver thread checks whether it was stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An address given either as an IP address or as a hostname, such as
+/// `studio.local:6980`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostAddr {
+    Ip(SocketAddr),
+    Name(String, u16),
+}
+
+impl HostAddr {
+    /// Parses an address as accepted by `net::parse_socket_addr`, or a
+    /// hostname with an optional port. `default_port` is used when the port
+    /// is missing.
+    pub fn parse(addr: &str, default_port: u16) -> Result<Self> {
+        let (host, port) = net::split_host_port(addr)?;
+        if is_hostname(host) && host.parse::<IpAddr>().is_err() {
+            return Ok(HostAddr::Name(
+                host.to_string(),
+                port.unwrap_or(default_port),
+            ));
+        }
+
+        Ok(HostAddr::Ip(net::parse_socket_addr(addr, default_port)?))
+    }
+
+    /// Every address of the host, as returned by the system resolver.
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        match self {
+            HostAddr::Ip(addr) => Ok(vec![*addr]),
+            HostAddr::Name(host, port) => resolve(host, *port),
+        }
+    }
+}
+
+impl fmt::Display for HostAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostAddr::Ip(addr) => write!(f, "{}", addr),
+            HostAddr::Name(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+/// Resolves a hostname with the system resolver, which also reads the hosts
+/// file and mDNS names where the system is set up for them.
+pub fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", host))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(anyhow!("{} has no address", host));
+    }
+
+    Ok(addrs)
+}
+
+/// Whether `host` only holds the characters of a hostname, which IPv4
+/// addresses also do.
+pub fn is_hostname(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+}
+
+/// Thread calling `refresh` every `RESOLVE_INTERVAL` until dropped.
+pub struct Resolver {
+    running: Arc<AtomicBool>,
+}
+
+impl Resolver {
+    pub fn spawn<F>(mut refresh: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+
+        thread::spawn(move || {
+            let mut next_refresh = Instant::now() + RESOLVE_INTERVAL;
+
+            while running_clone.load(Ordering::Relaxed) {
+                if Instant::now() < next_refresh {
+                    thread::sleep(STOP_POLL_INTERVAL);
+                    continue;
+                }
+                refresh();
+                next_refresh = Instant::now() + RESOLVE_INTERVAL;
+            }
+        });
+
+        Self { running }
+    }
+}
+
+impl Drop for Resolver {
+    fn drop(&mut self) {
+        // Not joined, as a lookup in progress can take seconds to time out
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_hostnames_apart() {
+        let cases = [
+            ("studio", true),
+            ("studio.local", true),
+            ("my-host_1.example.com", true),
+            ("192.168.0.1", true),
+            ("", false),
+            ("bad host", false),
+            ("::1", false),
+            ("fe80::1%eth0", false),
+            ("[::1]", false),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(is_hostname(host), expected, "{}", host);
+        }
+    }
+
+    #[test]
+    fn parses_host_addrs() {
+        let ip = |addr: &str| Some(HostAddr::Ip(addr.parse().unwrap()));
+        let name = |host: &str, port| Some(HostAddr::Name(host.to_string(), port));
+        let cases = [
+            ("192.168.0.1", ip("192.168.0.1:6980")),
+            ("192.168.0.1:7000", ip("192.168.0.1:7000")),
+            ("[::1]:7000", ip("[::1]:7000")),
+            ("fe80::1%2", ip("[fe80::1%2]:6980")),
+            ("studio.local", name("studio.local", 6980)),
+            ("studio.local:7000", name("studio.local", 7000)),
+            // Not a valid IPv4 address, so taken for a hostname
+            ("1.2.3.400", name("1.2.3.400", 6980)),
+            ("studio.local:port", None),
+            ("bad host", None),
+            ("[::1", None),
+            ("1.2.3.4%2", None),
+        ];
+        for (addr, expected) in cases {
+            assert_eq!(HostAddr::parse(addr, 6980).ok(), expected, "{}", addr);
+        }
+    }
+
+    #[test]
+    fn displays_host_addrs() {
+        assert_eq!(
+            HostAddr::Name("studio.local".to_string(), 6980).to_string(),
+            "studio.local:6980"
+        );
+        assert_eq!(
+            HostAddr::Ip("[::1]:6980".parse().unwrap()).to_string(),
+            "[::1]:6980"
+        );
+    }
+}